@@ -1,97 +1,830 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use bevy::{
+    asset::{AssetPath, HandleId, LoadState},
     ecs::system::{Command, EntityCommands},
     prelude::*,
+    reflect::{
+        DynamicArray, DynamicEnum, DynamicList, DynamicMap, DynamicStruct, DynamicTuple,
+        DynamicTupleStruct, DynamicVariant, Map, ReflectMut, ReflectRef, Struct, VariantType,
+    },
+    utils::HashMap,
 };
 
 use crate::{
-    prefab::{Prefab, PrefabCommandData, PrefabComponent},
+    prefab::{ParamRef, Prefab, PrefabBuildStep, PrefabCommandData, PrefabComponent},
+    registry::ReflectType,
+    reload::AddPrefabInstance,
     PrefabRegistry,
 };
 
 pub trait SpawnPrefabCommands {
     /// Apply [Prefab] components and commands to an entity.
     ///
-    /// Prefabs can be loaded from the [PrefabRegistry].
-    fn insert_prefab(&mut self, prefab: &Prefab) -> &mut Self;
+    /// Prefabs can be loaded from the [PrefabRegistry], or from the `AssetServer` as a
+    /// `Handle<Prefab>`. Prefabs inserted from a handle are applied once the prefab and any
+    /// prefabs it loads have finished loading.
+    fn insert_prefab(&mut self, prefab: impl InsertPrefab) -> &mut Self;
+
+    /// Apply a [Prefab] to an entity, giving values for the parameters declared in the
+    /// prefab's `params` block. Parameters that aren't given use their default value.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bevy::{prelude::*, reflect::DynamicStruct};
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// fn setup(mut commands: Commands, mut registry: ResMut<PrefabRegistry>) {
+    ///     let prefab = registry.load("goblin.prefab").unwrap();
+    ///     let mut args = DynamicStruct::default();
+    ///     args.insert("hp", 20);
+    ///     commands.spawn_empty().insert_prefab_with(prefab, args);
+    /// }
+    /// ```
+    fn insert_prefab_with(&mut self, prefab: impl InsertPrefab, args: DynamicStruct) -> &mut Self;
 }
 
 impl SpawnPrefabCommands for EntityCommands<'_, '_, '_> {
-    fn insert_prefab(&mut self, prefab: &Prefab) -> &mut Self {
-        let id = self.id();
-        for step in prefab.steps.iter() {
-            match step {
-                crate::prefab::PrefabBuildStep::AddComponent(comp) => {
-                    self.commands().add(AddComponentCommand {
-                        entity: id,
-                        component: comp.clone(),
-                    });
-                }
-                crate::prefab::PrefabBuildStep::RunCommand(command) => {
-                    self.commands().add(PrefabProcessCommand {
-                        entity: id,
-                        data: command.clone(),
-                    });
-                }
-            }
-        }
+    fn insert_prefab(&mut self, prefab: impl InsertPrefab) -> &mut Self {
+        prefab.insert_into(self, None);
+        self
+    }
 
+    fn insert_prefab_with(&mut self, prefab: impl InsertPrefab, args: DynamicStruct) -> &mut Self {
+        prefab.insert_into(self, Some(Arc::new(args)));
         self
     }
 }
 
-struct AddComponentCommand {
+/// A [Prefab] that can be applied to an entity with [SpawnPrefabCommands::insert_prefab].
+pub trait InsertPrefab {
+    /// Apply the prefab to the entity, with the arguments for it's parameters if any were
+    /// given.
+    fn insert_into(self, entity: &mut EntityCommands, args: Option<Arc<DynamicStruct>>);
+}
+
+impl InsertPrefab for &Prefab {
+    fn insert_into(self, entity: &mut EntityCommands, args: Option<Arc<DynamicStruct>>) {
+        Arc::new(self.clone()).insert_into(entity, args);
+    }
+}
+
+impl InsertPrefab for &Arc<Prefab> {
+    fn insert_into(self, entity: &mut EntityCommands, args: Option<Arc<DynamicStruct>>) {
+        self.clone().insert_into(entity, args);
+    }
+}
+
+impl InsertPrefab for Arc<Prefab> {
+    fn insert_into(self, entity: &mut EntityCommands, args: Option<Arc<DynamicStruct>>) {
+        let id = entity.id();
+        if let Some(path) = &self.path {
+            entity.commands().add(AddPrefabInstance {
+                entity: id,
+                name: path.clone(),
+                args: args.clone(),
+            });
+        }
+        entity.commands().add(ApplyPrefabCommand {
+            entity: id,
+            prefab: self,
+            args,
+        });
+    }
+}
+
+impl InsertPrefab for Handle<Prefab> {
+    fn insert_into(self, entity: &mut EntityCommands, args: Option<Arc<DynamicStruct>>) {
+        entity.insert(PendingPrefab(self, args));
+    }
+}
+
+impl InsertPrefab for &Handle<Prefab> {
+    fn insert_into(self, entity: &mut EntityCommands, args: Option<Arc<DynamicStruct>>) {
+        self.clone().insert_into(entity, args);
+    }
+}
+
+/// A prefab that will be applied to the entity once it's loaded, and the arguments for
+/// it's parameters.
+#[derive(Component)]
+pub(crate) struct PendingPrefab(Handle<Prefab>, Option<Arc<DynamicStruct>>);
+
+/// Apply prefabs inserted from a `Handle<Prefab>` once they're loaded.
+pub(crate) fn apply_pending_prefabs(world: &mut World) {
+    let mut query = world.query::<(Entity, &PendingPrefab)>();
+    let pending: Vec<_> = query
+        .iter(world)
+        .map(|(entity, pending)| (entity, pending.0.clone(), pending.1.clone()))
+        .collect();
+
+    for (entity, handle, args) in pending {
+        let server = world.resource::<AssetServer>();
+        if server.get_load_state(&handle) == LoadState::Failed {
+            error!(
+                "Error inserting prefab {:?} - the prefab failed to load.",
+                server.get_handle_path(&handle)
+            );
+            world.entity_mut(entity).remove::<PendingPrefab>();
+            continue;
+        }
+
+        if !prefab_loaded(world, handle.id(), &mut HashSet::new()) {
+            continue;
+        }
+
+        let prefab = world
+            .resource::<Assets<Prefab>>()
+            .get(&handle)
+            .unwrap()
+            .clone();
+        world.entity_mut(entity).remove::<PendingPrefab>();
+        if let Some(name) = prefab.path.clone() {
+            let args = args.clone();
+            AddPrefabInstance { entity, name, args }.write(world);
+        }
+        apply_prefab(world, entity, &prefab, args.as_deref());
+    }
+}
+
+/// Whether a prefab and every prefab it loads have finished loading. Prefabs that
+/// failed to load count as finished.
+fn prefab_loaded(world: &World, id: HandleId, visited: &mut HashSet<HandleId>) -> bool {
+    if !visited.insert(id) {
+        return true;
+    }
+
+    let prefab = match world.resource::<Assets<Prefab>>().get(&Handle::weak(id)) {
+        Some(prefab) => prefab,
+        None => return world.resource::<AssetServer>().get_load_state(id) == LoadState::Failed,
+    };
+
+    // Only weak handles are used for the loaded prefabs, so they aren't freed when unused.
+    prefab.dependencies().iter().all(|name| {
+        let id = HandleId::from(AssetPath::from(name.as_str()));
+        prefab_loaded(world, id, visited)
+    })
+}
+
+struct ApplyPrefabCommand {
+    entity: Entity,
+    prefab: Arc<Prefab>,
+    args: Option<Arc<DynamicStruct>>,
+}
+
+impl Command for ApplyPrefabCommand {
+    fn write(self, world: &mut World) {
+        apply_prefab(world, self.entity, &self.prefab, self.args.as_deref());
+    }
+}
+
+/// The entities spawned for a prefab, by their names in the prefab.
+type EntityNames = HashMap<String, Entity>;
+
+/// The values of a prefab's parameters, by name.
+type ParamValues<'a> = HashMap<&'a str, &'a dyn Reflect>;
+
+struct AddComponentCommand<'a> {
     entity: Entity,
-    component: Arc<PrefabComponent>,
+    component: &'a PrefabComponent,
+    names: &'a EntityNames,
+    params: &'a ParamValues<'a>,
 }
 
-impl Command for AddComponentCommand {
+impl AddComponentCommand<'_> {
     fn write(self, world: &mut World) {
         let entity = self.entity;
         let component = self.component;
+        let type_name = component.type_name.as_str();
 
-        let registry = world.get_resource::<PrefabRegistry>().unwrap();
+        // Components are checked when the prefab is loaded, so these only fail if the
+        // registry was changed or removed since.
+        let reflect = world
+            .get_resource::<PrefabRegistry>()
+            .and_then(|registry| registry.get_type_data(type_name))
+            .and_then(|t| t.registration.data::<ReflectComponent>())
+            .cloned();
+        let reflect = match reflect {
+            Some(reflect) => reflect,
+            None => {
+                error!(
+                    "Error adding prefab component {}. Is it registered in the PrefabRegistry?",
+                    type_name
+                );
+                return;
+            }
+        };
 
-        let reg = &registry
-            .get_type_data(component.type_name.as_str())
-            .unwrap()
-            .registration;
-        let type_id = reg.type_id();
+        if world.get_entity(entity).is_none() {
+            warn!(
+                "Error adding prefab component {}. The entity {:?} doesn't exist.",
+                type_name, entity
+            );
+            return;
+        }
 
-        let reflect = match reg.data::<ReflectComponent>() {
-            Some(reflect) => reflect,
-            None => panic!("Error reading reflect data. Does the type {} have the '#[reflect(Component)]' attribute?", reg.short_name()),
-        }.clone();
+        if component.entity_refs.is_empty() && !component.uses_params {
+            reflect.apply_or_insert(world, entity, &*component.reflect);
+            return;
+        }
+
+        let mut value = match component.uses_params {
+            true => substitute_params(&*component.reflect, self.params),
+            false => component.reflect.clone_value(),
+        };
+        if component.entity_refs.is_empty() {
+            reflect.apply_or_insert(world, entity, &*value);
+            return;
+        }
+
+        // Entity references are stored as placeholders, which are replaced by the entities
+        // spawned for the prefab.
+        map_entities(&mut *value, &mut |placeholder| {
+            let name = match PrefabComponent::placeholder_index(placeholder) {
+                Some(index) => &component.entity_refs[index],
+                None => return placeholder,
+            };
+            match self.names.get(name) {
+                Some(entity) => *entity,
+                None => {
+                    error!(
+                        "Error adding prefab component {}. No entity is named '#{}'.",
+                        type_name, name
+                    );
+                    placeholder
+                }
+            }
+        });
+        reflect.apply_or_insert(world, entity, &*value);
+    }
+}
+
+/// Replace every `Entity` in a reflected value, like `MapEntities` does for components.
+pub(crate) fn map_entities(value: &mut dyn Reflect, map: &mut dyn FnMut(Entity) -> Entity) {
+    if let Some(entity) = value.downcast_mut::<Entity>() {
+        *entity = map(*entity);
+        return;
+    }
+
+    match value.reflect_mut() {
+        ReflectMut::Struct(value) => {
+            for i in 0..value.field_len() {
+                map_entities(value.field_at_mut(i).unwrap(), map);
+            }
+        }
+        ReflectMut::TupleStruct(value) => {
+            for i in 0..value.field_len() {
+                map_entities(value.field_mut(i).unwrap(), map);
+            }
+        }
+        ReflectMut::Tuple(value) => {
+            for i in 0..value.field_len() {
+                map_entities(value.field_mut(i).unwrap(), map);
+            }
+        }
+        ReflectMut::List(value) => {
+            for i in 0..value.len() {
+                map_entities(value.get_mut(i).unwrap(), map);
+            }
+        }
+        ReflectMut::Array(value) => {
+            for i in 0..value.len() {
+                map_entities(value.get_mut(i).unwrap(), map);
+            }
+        }
+        ReflectMut::Map(value) => {
+            let keys: Vec<_> = (0..value.len())
+                .filter_map(|i| value.get_at(i))
+                .map(|(key, _)| key.clone_value())
+                .collect();
+            for key in keys {
+                if let Some(value) = value.get_mut(&*key) {
+                    map_entities(value, map);
+                }
+            }
+        }
+        ReflectMut::Enum(value) => {
+            for i in 0..value.field_len() {
+                map_entities(value.field_at_mut(i).unwrap(), map);
+            }
+        }
+        ReflectMut::Value(_) => {}
+    }
+}
+
+/// Copy a reflected value, replacing every [ParamRef] in it with the parameter's value.
+///
+/// Containers are copied as their dynamic types, which apply to the real types the same way.
+fn substitute_params(value: &dyn Reflect, params: &ParamValues) -> Box<dyn Reflect> {
+    if let Some(param) = value.downcast_ref::<ParamRef>() {
+        // References are checked when the prefab is loaded, so the parameter always exists.
+        return match params.get(param.0.as_str()) {
+            Some(param) => param.clone_value(),
+            None => value.clone_value(),
+        };
+    }
 
-        if world.entity(entity).contains_type_id(type_id) {
-            reflect.apply(world, entity, &*component.reflect);
-        } else {
-            reflect.insert(world, entity, &*component.reflect);
+    match value.reflect_ref() {
+        ReflectRef::Struct(value) => Box::new(substitute_struct(value, params)),
+        ReflectRef::TupleStruct(value) => {
+            let mut copy = DynamicTupleStruct::default();
+            copy.set_name(value.type_name().to_string());
+            for field in value.iter_fields() {
+                copy.insert_boxed(substitute_params(field, params));
+            }
+            Box::new(copy)
+        }
+        ReflectRef::Tuple(value) => {
+            let mut copy = DynamicTuple::default();
+            copy.set_name(value.type_name().to_string());
+            for field in value.iter_fields() {
+                copy.insert_boxed(substitute_params(field, params));
+            }
+            Box::new(copy)
+        }
+        ReflectRef::List(value) => {
+            let mut copy = DynamicList::default();
+            copy.set_name(value.type_name().to_string());
+            for item in value.iter() {
+                copy.push_box(substitute_params(item, params));
+            }
+            Box::new(copy)
+        }
+        ReflectRef::Array(value) => {
+            let items: Vec<_> = value
+                .iter()
+                .map(|item| substitute_params(item, params))
+                .collect();
+            let mut copy = DynamicArray::new(items.into_boxed_slice());
+            copy.set_name(value.type_name().to_string());
+            Box::new(copy)
         }
+        ReflectRef::Map(value) => {
+            let mut copy = DynamicMap::default();
+            copy.set_name(value.type_name().to_string());
+            for (key, item) in value.iter() {
+                copy.insert_boxed(key.clone_value(), substitute_params(item, params));
+            }
+            Box::new(copy)
+        }
+        ReflectRef::Enum(value) => {
+            let variant = match value.variant_type() {
+                VariantType::Unit => DynamicVariant::Unit,
+                VariantType::Tuple => {
+                    let mut fields = DynamicTuple::default();
+                    for field in value.iter_fields() {
+                        fields.insert_boxed(substitute_params(field.value(), params));
+                    }
+                    DynamicVariant::Tuple(fields)
+                }
+                VariantType::Struct => {
+                    let mut fields = DynamicStruct::default();
+                    for field in value.iter_fields() {
+                        let name = field.name().unwrap();
+                        fields.insert_boxed(name, substitute_params(field.value(), params));
+                    }
+                    DynamicVariant::Struct(fields)
+                }
+            };
+            Box::new(DynamicEnum::new(
+                value.type_name(),
+                value.variant_name(),
+                variant,
+            ))
+        }
+        ReflectRef::Value(value) => value.clone_value(),
     }
 }
 
-pub struct PrefabProcessCommand {
+fn substitute_struct(value: &dyn Struct, params: &ParamValues) -> DynamicStruct {
+    let mut copy = DynamicStruct::default();
+    copy.set_name(value.type_name().to_string());
+    for (i, field) in value.iter_fields().enumerate() {
+        let name = value.name_at(i).unwrap();
+        copy.insert_boxed(name, substitute_params(field, params));
+    }
+    copy
+}
+
+/// The values of a prefab's parameters, using the given arguments in place of the defaults.
+fn param_values<'a>(prefab: &'a Prefab, args: Option<&'a DynamicStruct>) -> ParamValues<'a> {
+    let mut params: ParamValues = prefab
+        .params
+        .iter()
+        .map(|param| (param.name.as_str(), &*param.default))
+        .collect();
+
+    for (i, value) in args.iter().flat_map(|args| args.iter_fields().enumerate()) {
+        let name = args.unwrap().name_at(i).unwrap();
+        match params.get_mut(name) {
+            Some(param) if same_type(*param, value) => *param = value,
+            Some(param) => error!(
+                "Error inserting prefab - the argument '{}' should be a {}, found {}.",
+                name,
+                param.type_name(),
+                value.type_name()
+            ),
+            None => warn!(
+                "Error inserting prefab - the prefab has no parameter named '{}'.",
+                name
+            ),
+        }
+    }
+    params
+}
+
+/// Whether an argument can be used for a parameter. Dynamic values match any type of the
+/// same kind, since they don't know the type they represent.
+fn same_type(param: &dyn Reflect, arg: &dyn Reflect) -> bool {
+    if param.type_name() == arg.type_name() {
+        return true;
+    }
+    let kind = ReflectType::from(param.reflect_ref());
+    kind != ReflectType::Value && kind == ReflectType::from(arg.reflect_ref())
+}
+
+struct PrefabProcessCommand<'a> {
     entity: Entity,
-    data: Arc<PrefabCommandData>,
+    data: &'a PrefabCommandData,
+    params: &'a ParamValues<'a>,
 }
 
-impl Command for PrefabProcessCommand {
+impl PrefabProcessCommand<'_> {
     fn write(self, world: &mut World) {
         let entity = self.entity;
         let data = self.data;
         let command_name = data.name.as_str();
 
-        let reg = world.get_resource::<PrefabRegistry>().unwrap();
-        let command = reg.get_build_command(command_name).unwrap_or_else(|| {
-            panic!(
-                "Error performing prefab command {}. Was it registered in the PrefabRegistry?",
-                command_name
+        // The registry isn't borrowed while the command runs, so commands can load prefabs.
+        let command = world
+            .get_resource::<PrefabRegistry>()
+            .and_then(|reg| reg.get_build_command(command_name))
+            .cloned();
+        let command = match command {
+            Some(command) => command,
+            None => {
+                error!(
+                    "Error performing prefab command {}. Was it registered in the PrefabRegistry?",
+                    command_name
+                );
+                return;
+            }
+        };
+
+        if world.get_entity(entity).is_none() {
+            warn!(
+                "Error performing prefab command {}. The entity {:?} doesn't exist.",
+                command_name, entity
+            );
+            return;
+        }
+
+        let substituted;
+        let properties = match &data.properties {
+            Some(properties) if data.uses_params => {
+                substituted = substitute_struct(properties, self.params);
+                Some(&substituted)
+            }
+            properties => properties.as_ref(),
+        };
+        command.run(properties, world, entity);
+    }
+}
+
+/// Marks entities that were spawned as children of a prefab entity.
+#[derive(Component, Default)]
+pub(crate) struct PrefabChild;
+
+/// Immediately spawn a [Prefab]'s children and perform it's build steps on an entity.
+///
+/// Every child is spawned before any build steps are performed, so components can refer to
+/// any named entity in the prefab. `args` are the values for the prefab's parameters, which
+/// are shared with it's children.
+pub(crate) fn apply_prefab(
+    world: &mut World,
+    entity: Entity,
+    prefab: &Prefab,
+    args: Option<&DynamicStruct>,
+) {
+    if world.get_entity(entity).is_none() {
+        warn!(
+            "Error inserting prefab. The entity {:?} doesn't exist.",
+            entity
+        );
+        return;
+    }
+
+    let params = param_values(prefab, args);
+    let mut entities = Vec::new();
+    let mut names = EntityNames::default();
+    spawn_children(world, entity, prefab, &mut entities, &mut names);
+
+    for (entity, prefab) in entities {
+        for step in prefab.steps.iter() {
+            match step {
+                PrefabBuildStep::AddComponent(component) => AddComponentCommand {
+                    entity,
+                    component,
+                    names: &names,
+                    params: &params,
+                }
+                .write(world),
+                PrefabBuildStep::RunCommand(command) => PrefabProcessCommand {
+                    entity,
+                    data: command,
+                    params: &params,
+                }
+                .write(world),
+                PrefabBuildStep::SpawnChild(_) => {}
+            }
+        }
+    }
+}
+
+/// Spawn the children of a prefab entity, and their children, recording the prefab for
+/// each entity and the names of named entities.
+fn spawn_children<'a>(
+    world: &mut World,
+    entity: Entity,
+    prefab: &'a Prefab,
+    entities: &mut Vec<(Entity, &'a Prefab)>,
+    names: &mut EntityNames,
+) {
+    entities.push((entity, prefab));
+    if let Some(name) = &prefab.entity_name {
+        names.insert(name.clone(), entity);
+    }
+
+    for step in prefab.steps.iter() {
+        if let PrefabBuildStep::SpawnChild(child) = step {
+            let child_entity = world.spawn(PrefabChild).id();
+            world.entity_mut(entity).push_children(&[child_entity]);
+            spawn_children(world, child_entity, child, entities, names);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::{
+        asset::{AssetPath, HandleId},
+        ecs::system::CommandQueue,
+        prelude::*,
+        reflect::DynamicStruct,
+    };
+
+    use crate::{
+        bevy_commands::{apply_prefab, PendingPrefab},
+        build_commands::{BuildPrefabCommand, LoadPrefab},
+        parse::parse_prefab_file,
+        plugin::LazyPrefabsMinimalPlugin,
+        MemoryPrefabSource, Prefab, PrefabRegistry, SpawnPrefabCommands,
+    };
+
+    #[derive(Debug, Default, Reflect, FromReflect)]
+    struct Damage {
+        amount: i32,
+        kind: String,
+    }
+
+    #[derive(Debug, Default, Reflect, Component)]
+    #[reflect(Component)]
+    struct Weapon {
+        name: String,
+        damage: Damage,
+    }
+
+    #[derive(Debug, Default, Reflect, Component)]
+    #[reflect(Component)]
+    struct Aim {
+        target: Option<Entity>,
+        owners: Vec<Entity>,
+    }
+
+    /// A build command that names the entity it runs on.
+    #[derive(Default)]
+    struct Mark;
+    impl BuildPrefabCommand for Mark {
+        fn run(&self, _: Option<&DynamicStruct>, world: &mut World, entity: Entity) {
+            world.entity_mut(entity).insert(Name::new("marked"));
+        }
+
+        fn key(&self) -> &str {
+            "Mark"
+        }
+    }
+
+    fn registry() -> PrefabRegistry {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Transform>();
+        registry.register_type::<Visibility>();
+        registry.register_type::<Weapon>();
+        registry.register_type::<Damage>();
+        registry.register_type::<Aim>();
+        registry.register_build_command::<Mark>();
+        registry.clear_sources();
+        registry
+    }
+
+    #[test]
+    fn spawn_children() {
+        let registry = registry();
+        let input = "Tank {
+            Transform,
+            children: [
+                Turret {
+                    Transform { translation: Vec3 { y: 1.0 } },
+                    children: [{ Visibility }],
+                },
+                { Mark!() },
+            ],
+        }";
+        let prefab = parse_prefab_file("tank.prefab", input, &registry).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(registry);
+        let tank = world.spawn_empty().id();
+        let mut queue = CommandQueue::default();
+        Commands::new(&mut queue, &world)
+            .entity(tank)
+            .insert_prefab(&prefab);
+        queue.apply(&mut world);
+
+        let children = world.get::<Children>(tank).unwrap();
+        assert_eq!(children.len(), 2);
+        let (turret, marked) = (children[0], children[1]);
+        assert_eq!(world.get::<Parent>(turret).unwrap().get(), tank);
+        assert_eq!(
+            world.get::<Transform>(turret).unwrap().translation,
+            Vec3::new(0.0, 1.0, 0.0)
+        );
+        assert!(world.get::<Name>(marked).is_some());
+
+        let turret_children = world.get::<Children>(turret).unwrap();
+        assert_eq!(turret_children.len(), 1);
+        assert!(world.get::<Visibility>(turret_children[0]).is_some());
+    }
+
+    #[test]
+    fn map_entity_references() {
+        let registry = registry();
+        let input = "Tank #tank {
+            Aim { target: Some(@muzzle), owners: [@tank] },
+            children: [
+                Turret {
+                    Aim { owners: [@tank, @muzzle] },
+                    children: [#muzzle { Transform }],
+                },
+            ],
+        }";
+        let prefab = parse_prefab_file("tank.prefab", input, &registry).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(registry);
+        let tank = world.spawn_empty().id();
+        apply_prefab(&mut world, tank, &prefab, None);
+
+        let turret = world.get::<Children>(tank).unwrap()[0];
+        let muzzle = world.get::<Children>(turret).unwrap()[0];
+        let aim = world.get::<Aim>(tank).unwrap();
+        assert_eq!(aim.target, Some(muzzle));
+        assert_eq!(aim.owners, vec![tank]);
+        assert_eq!(world.get::<Aim>(turret).unwrap().owners, vec![tank, muzzle]);
+    }
+
+    #[test]
+    fn apply_params() {
+        let registry = registry();
+        let input = "{
+            params { amount: i32 = 10, kind: String = \"slash\" },
+            Weapon { name: \"Sword\", damage: Damage { amount: $amount, kind: $kind } },
+            children: [{ Weapon { damage: Damage { amount: $amount } } }],
+        }";
+        let prefab = parse_prefab_file("sword.prefab", input, &registry).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(registry);
+        let sword = world.spawn_empty().id();
+        apply_prefab(&mut world, sword, &prefab, None);
+        assert_eq!(world.get::<Weapon>(sword).unwrap().damage.amount, 10);
+        assert_eq!(world.get::<Weapon>(sword).unwrap().damage.kind, "slash");
+
+        // Parameters that aren't given keep their default, and children share the arguments.
+        let mut args = DynamicStruct::default();
+        args.insert("amount", 25);
+        let sword = world.spawn_empty().id();
+        apply_prefab(&mut world, sword, &prefab, Some(&args));
+        let weapon = world.get::<Weapon>(sword).unwrap();
+        assert_eq!(weapon.name, "Sword");
+        assert_eq!(weapon.damage.amount, 25);
+        assert_eq!(weapon.damage.kind, "slash");
+        let child = world.get::<Children>(sword).unwrap()[0];
+        assert_eq!(world.get::<Weapon>(child).unwrap().damage.amount, 25);
+    }
+
+    #[test]
+    fn apply_inherited_prefab() {
+        let mut registry = registry();
+        registry.add_source(MemoryPrefabSource::from_iter([(
+            "sword.prefab",
+            "Sword { Weapon { name: \"Sword\", damage: Damage { amount: 5, kind: \"slash\" } } }",
+        )]));
+        let input = "SharpSword : \"sword.prefab\" {
+            Visibility,
+            Weapon { damage: Damage { amount: 10 } },
+        }";
+        let prefab = parse_prefab_file("sharp_sword.prefab", input, &registry).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(registry);
+        let entity = world.spawn_empty().id();
+        apply_prefab(&mut world, entity, &prefab, None);
+        let weapon = world.get::<Weapon>(entity).unwrap();
+        assert_eq!(weapon.name, "Sword");
+        assert_eq!(weapon.damage.amount, 10);
+        assert_eq!(weapon.damage.kind, "slash");
+        assert!(world.get::<Visibility>(entity).is_some());
+    }
+
+    #[test]
+    fn apply_component_groups() {
+        let mut registry = registry();
+        registry.add_source(MemoryPrefabSource::from_iter([(
+            "common.prefab",
+            "group Visible { Visibility, Transform { translation: Vec3 { x: 1.0 } } }",
+        )]));
+        let input = "import \"common.prefab\"
+            group Scaled { ..Visible, Transform { scale: Vec3 { x: 2.0 } } }
+            Thing { ..Scaled, Visibility { is_visible: false } }";
+        let prefab = parse_prefab_file("thing.prefab", input, &registry).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(registry);
+        let entity = world.spawn_empty().id();
+        apply_prefab(&mut world, entity, &prefab, None);
+        let transform = world.get::<Transform>(entity).unwrap();
+        assert_eq!(transform.translation.x, 1.0);
+        assert_eq!(transform.scale.x, 2.0);
+        assert!(!world.get::<Visibility>(entity).unwrap().is_visible);
+    }
+
+    #[test]
+    fn insert_prefab_handle() {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_plugin(LazyPrefabsMinimalPlugin);
+
+        let mut registry = app.world.resource_mut::<PrefabRegistry>();
+        registry.register_type::<Transform>();
+        registry.register_type::<Visibility>();
+        registry.register_build_command::<LoadPrefab>();
+        let blade = registry
+            .load_from_str("blade.prefab", "{ Visibility { is_visible: false } }")
+            .unwrap()
+            .as_ref()
+            .clone();
+        let sword = registry
+            .load_from_str(
+                "sword.prefab",
+                "{ LoadPrefab!(name: \"blade.prefab\"), Transform { translation: Vec3 { x: 2.0 } } }",
             )
-        });
+            .unwrap()
+            .as_ref()
+            .clone();
+
+        // The handle's asset hasn't been loaded yet.
+        let id = |name: &str| HandleId::from(AssetPath::from(name));
+        let handle = app
+            .world
+            .resource::<Assets<Prefab>>()
+            .get_handle(id("sword.prefab"));
+        let mut queue = CommandQueue::default();
+        let entity = Commands::new(&mut queue, &app.world)
+            .spawn_empty()
+            .insert_prefab(&handle)
+            .id();
+        queue.apply(&mut app.world);
+
+        app.update();
+        assert!(app.world.get::<PendingPrefab>(entity).is_some());
+        assert!(app.world.get::<Transform>(entity).is_none());
+
+        // The prefab is applied once every prefab it loads has loaded too.
+        let mut assets = app.world.resource_mut::<Assets<Prefab>>();
+        assets.set_untracked(id("sword.prefab"), sword);
+        app.update();
+        assert!(app.world.get::<Transform>(entity).is_none());
 
-        let command = command.clone();
-        command.run(data.properties.as_ref(), world, entity);
+        let mut assets = app.world.resource_mut::<Assets<Prefab>>();
+        assets.set_untracked(id("blade.prefab"), blade);
+        app.update();
+        assert!(app.world.get::<PendingPrefab>(entity).is_none());
+        assert_eq!(
+            app.world.get::<Transform>(entity).unwrap().translation,
+            Vec3::new(2.0, 0.0, 0.0)
+        );
+        assert!(!app.world.get::<Visibility>(entity).unwrap().is_visible);
     }
 }
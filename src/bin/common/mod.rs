@@ -0,0 +1,52 @@
+//! Code shared by the prefab binaries.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The path if it's a file, or every *.prefab* file in it and it's subfolders if it's a
+/// folder, sorted by path.
+pub fn find_prefabs(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    find_prefabs_in(path.as_ref(), &mut files)?;
+    Ok(files)
+}
+
+fn find_prefabs_in(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            find_prefabs_in(&entry, files)?;
+        } else if entry.extension().and_then(|ext| ext.to_str()) == Some("prefab") {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::find_prefabs;
+
+    #[test]
+    fn find_prefab_files() {
+        let files = find_prefabs("assets").unwrap();
+        assert!(files.iter().all(|f| f.extension().unwrap() == "prefab"));
+        assert!(files.windows(2).all(|w| w[0] < w[1]));
+        assert!(files.contains(&"assets/bird.prefab".into()));
+
+        let files = find_prefabs("assets/bird.prefab").unwrap();
+        assert_eq!(files, vec![PathBuf::from("assets/bird.prefab")]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Checks *.prefab* files against a type manifest.
+//!
+//! ```text
+//! prefab-check --manifest types.json [PATH]...
+//! ```
+//!
+//! Each path is a *.prefab* file or a directory to search for them. Prefabs are checked by a
+//! registry the manifest's types are added to, so every error the game would report is
+//! reported, and the exit code is non-zero if there were any. Prefabs inherit from and import
+//! other prefabs relative to the directory given, or the file's directory. See
+//! [bevy_lazy_prefabs::TypeManifest] for the manifest format.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use bevy_lazy_prefabs::{
+    FilePrefabSource, LoadPrefabError, ManifestError, PrefabDiagnostic, PrefabRegistry,
+    TypeManifest,
+};
+
+mod common;
+
+use common::find_prefabs;
+
+const USAGE: &str = "Usage: prefab-check --manifest <FILE> [PATH]...";
+
+fn main() -> ExitCode {
+    let mut manifest_path = None;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest" => manifest_path = args.next(),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown option '{}'.\n{}", arg, USAGE);
+                return ExitCode::FAILURE;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let manifest_path = match manifest_path {
+        Some(path) if !paths.is_empty() => path,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let manifest = match TypeManifest::from_file(&manifest_path) {
+        Ok(manifest) => manifest,
+        Err(ManifestError::ReadError(path, e)) => {
+            eprintln!("Error reading '{}': {}", path, e);
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut registry = PrefabRegistry::default();
+    registry.register_manifest(&manifest);
+
+    let mut checked = 0;
+    let mut errors = 0;
+    for path in paths {
+        let root = prefab_root(&path);
+        let found = fs::canonicalize(root)
+            .and_then(|full_root| Ok((FilePrefabSource::new(full_root), find_prefabs(&path)?)));
+        let (source, files) = match found {
+            Ok(found) => found,
+            Err(e) => {
+                eprintln!("Error reading '{}': {}", path.display(), e);
+                return ExitCode::FAILURE;
+            }
+        };
+        registry.clear_sources();
+        registry.add_source(source);
+
+        for file in files {
+            let name = file.strip_prefix(root).unwrap_or(&file);
+            let name = name.to_string_lossy().replace('\\', "/");
+            for diagnostic in registry.diagnose(&name) {
+                eprintln!("{}", relative_to(root, diagnostic));
+                errors += 1;
+            }
+            checked += 1;
+        }
+    }
+
+    eprintln!("Checked {} prefab files, found {} errors.", checked, errors);
+    match errors {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+/// The directory prefabs are read from: the path if it's a directory, or the file's directory.
+fn prefab_root(path: &Path) -> &Path {
+    match path.parent() {
+        _ if path.is_dir() => path,
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Show the path of a diagnostic's location as it was given on the command line.
+fn relative_to(root: &Path, mut diagnostic: PrefabDiagnostic) -> PrefabDiagnostic {
+    if let LoadPrefabError::Located { location, .. } = &mut diagnostic.error {
+        if let Some(path) = &mut location.path {
+            *path = root.join(&*path).display().to_string();
+        }
+    }
+    diagnostic
+}
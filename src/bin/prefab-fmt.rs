@@ -0,0 +1,115 @@
+//! Formats *.prefab* files.
+//!
+//! ```text
+//! prefab-fmt [--check] [PATH]...
+//! ```
+//!
+//! Each path is a *.prefab* file or a directory to search for them. Files are formatted in
+//! place, or with `--check` the unformatted files are listed and the exit code is non-zero.
+//! With no paths the prefab is read from stdin and written to stdout.
+
+use std::{
+    env, fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use bevy_lazy_prefabs::format_prefab;
+
+mod common;
+
+use common::find_prefabs;
+
+const USAGE: &str = "Usage: prefab-fmt [--check] [PATH]...";
+
+fn main() -> ExitCode {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown option '{}'.\n{}", arg, USAGE);
+                return ExitCode::FAILURE;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        return format_stdin(check);
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        match find_prefabs(&path) {
+            Ok(found) => files.extend(found),
+            Err(e) => {
+                eprintln!("Error reading '{}': {}", path.display(), e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let mut success = true;
+    for file in files {
+        let input = match fs::read_to_string(&file) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Error reading '{}': {}", file.display(), e);
+                success = false;
+                continue;
+            }
+        };
+        let formatted = match format_prefab(&input) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("Error formatting '{}': {}", file.display(), e);
+                success = false;
+                continue;
+            }
+        };
+        if formatted == input {
+            continue;
+        }
+        if check {
+            println!("{}", file.display());
+            success = false;
+        } else if let Err(e) = fs::write(&file, formatted) {
+            eprintln!("Error writing '{}': {}", file.display(), e);
+            success = false;
+        }
+    }
+
+    match success {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+fn format_stdin(check: bool) -> ExitCode {
+    let mut input = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut input) {
+        eprintln!("Error reading stdin: {}", e);
+        return ExitCode::FAILURE;
+    }
+    match format_prefab(&input) {
+        Ok(formatted) if check => match formatted == input {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        },
+        Ok(formatted) => {
+            print!("{}", formatted);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error formatting stdin: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,8 +1,14 @@
 //! Commands used for handling more complex prefab entity initialization, such as bundles, materials, and meshes.
 
-use bevy::{prelude::*, reflect::DynamicStruct};
+use bevy::{
+    asset::{AssetPath, HandleId},
+    prelude::*,
+    reflect::DynamicStruct,
+};
 
-use crate::{dynamic_cast::*, PrefabRegistry};
+use crate::{
+    bevy_commands::apply_prefab, dynamic_cast::*, LoadPrefabError, Prefab, PrefabRegistry,
+};
 
 /// A build command for handling more complex prefab entity initialization.
 ///
@@ -16,7 +22,7 @@ pub trait BuildPrefabCommand {
     /// ### Arguments
     ///
     ///  - `properties` - An optional  [DynamicStruct] containing any properties read
-    /// from the *.prefab* file. [None] if no properties were receieved.
+    ///    from the *.prefab* file. [None] if no properties were receieved.
     ///  - `entity` - The prefab entity, to be modified as needed.
     ///
     /// ### Example
@@ -40,6 +46,17 @@ pub trait BuildPrefabCommand {
     /// The key for this command. This is the name you refer to the command by
     /// from your *.prefab* file.
     fn key(&self) -> &str;
+
+    /// The properties the command reads, as `(name, type)` pairs, ie: `("scale", "f32")`.
+    /// Properties that accept more than one type list them separated by `|`, ie: `"f32 | Vec2"`.
+    ///
+    /// Property values are parsed as their declared type, and unknown properties are reported
+    /// when the prefab is loaded. Types the registry doesn't know are parsed as written.
+    /// Properties are listed in the registry's [crate::TypeManifest], so tools can check them
+    /// without the game. Commands that don't declare any properties accept any properties.
+    fn properties(&self) -> &[(&str, &str)] {
+        &[]
+    }
 }
 
 /// Sets [ColorMaterial] values on the entity.
@@ -74,6 +91,10 @@ impl BuildPrefabCommand for SetColorMaterial {
     fn key(&self) -> &str {
         "SetColorMaterial"
     }
+
+    fn properties(&self) -> &[(&str, &str)] {
+        &[("color", "Color"), ("texture_path", "String")]
+    }
 }
 
 fn get_material_props(properties: Option<&DynamicStruct>) -> (Option<Color>, Option<String>) {
@@ -118,53 +139,74 @@ fn get_color_material(
 ///
 /// ### Required Property:
 ///
-/// - `name` - The name of the prefab, including the extension.
+/// - `name` - The name of the prefab, including the extension. Prefabs in a file with
+///   several prefabs are named after the file, ie: `enemies.prefab#Orc`.
 #[derive(Default)]
 pub struct LoadPrefab;
 impl BuildPrefabCommand for LoadPrefab {
     fn run(&self, properties: Option<&DynamicStruct>, world: &mut World, entity: Entity) {
-        if let Some(props) = properties {
-            if let Ok(name) = props.try_get::<String>("name") {
-                world.resource_scope(|world, mut reg: Mut<PrefabRegistry>| {
-
-                    let prefab = reg.load(name.as_str()).unwrap().clone();
-
-                    for step in prefab.steps.iter() {
-                        match step {
-                            crate::prefab::PrefabBuildStep::AddComponent(comp) => {
-                                let reg = &reg
-                                    .get_type_data(comp.type_name.as_str())
-                                    .unwrap()
-                                    .registration;
-                                let type_id = reg.type_id();
-                                let reflect = match reg.data::<ReflectComponent>() {
-                                    Some(reflect) => reflect,
-                                    None => panic!("Error reading reflect data.
-                                        Does the type {} have the '#[reflect(Component)]' attribute?", reg.short_name()),
-                                }.clone();
-                                if world.entity(entity).contains_type_id(type_id) {
-                                    reflect.apply(world, entity, &*comp.reflect);
-                                } else {
-                                    reflect.insert(world, entity, &*comp.reflect);
-                                }
-                            },
-                            crate::prefab::PrefabBuildStep::RunCommand(data) => {
-                                let cmd = reg.get_build_command(data.name.as_str()).unwrap();
-
-                                cmd.run(data.properties.as_ref(), world, entity);
-                            },
-                        }
-                    }
-                });
+        let name = match properties.and_then(|props| props.try_get::<String>("name").ok()) {
+            Some(name) => name,
+            None => {
+                error!("Error running LoadPrefab - the 'name' property is missing.");
+                return;
             }
+        };
+
+        // Use the prefab asset if it was loaded along with a `Handle<Prefab>`.
+        let id = HandleId::from(AssetPath::from(name.as_str()));
+        let asset = world
+            .get_resource::<Assets<Prefab>>()
+            .and_then(|prefabs| prefabs.get(&Handle::weak(id)))
+            .cloned();
+
+        let prefab = match asset {
+            Some(prefab) => prefab,
+            None => match world.get_resource_mut::<PrefabRegistry>() {
+                Some(mut reg) => match reg.load(name.as_str()) {
+                    Ok(prefab) => prefab.as_ref().clone(),
+                    Err(e) => {
+                        error!("Error running LoadPrefab - {}", e);
+                        return;
+                    }
+                },
+                None => {
+                    error!("Error running LoadPrefab - the PrefabRegistry resource is missing.");
+                    return;
+                }
+            },
+        };
+
+        // The registry rejects cycles when loading, but prefab assets are loaded separately
+        // so they're checked as they're applied.
+        let mut loading = world.get_resource_or_insert_with(LoadingPrefabs::default);
+        if let Some(start) = loading.0.iter().position(|n| *n == name) {
+            let mut cycle = loading.0[start..].to_vec();
+            cycle.push(name);
+            error!(
+                "Error running LoadPrefab - {}",
+                LoadPrefabError::Cycle(cycle)
+            );
+            return;
         }
+        loading.0.push(name);
+        apply_prefab(world, entity, &prefab, None);
+        world.resource_mut::<LoadingPrefabs>().0.pop();
     }
 
     fn key(&self) -> &str {
         "LoadPrefab"
     }
+
+    fn properties(&self) -> &[(&str, &str)] {
+        &[("name", "String")]
+    }
 }
 
+/// The prefabs currently being applied by [LoadPrefab], outermost first.
+#[derive(Resource, Default)]
+struct LoadingPrefabs(Vec<String>);
+
 /// Inserts a [SpriteBundle].
 ///
 /// ### Optional Properties:
@@ -195,6 +237,10 @@ impl BuildPrefabCommand for InsertSpriteBundle {
     fn key(&self) -> &str {
         "InsertSpriteBundle"
     }
+
+    fn properties(&self) -> &[(&str, &str)] {
+        &[("color", "Color"), ("texture_path", "String")]
+    }
 }
 
 /// Inserts a [PbrBundle].
@@ -232,6 +278,15 @@ impl BuildPrefabCommand for InsertPbrBundle {
     fn key(&self) -> &str {
         "InsertPbrBundle"
     }
+
+    fn properties(&self) -> &[(&str, &str)] {
+        &[
+            ("shape", "Shape"),
+            ("size", "f32 | Vec2"),
+            ("flip", "bool"),
+            ("color", "Color"),
+        ]
+    }
 }
 
 fn get_mesh(props: &DynamicStruct) -> Option<Mesh> {
@@ -279,6 +334,10 @@ impl BuildPrefabCommand for InsertOrthographicCameraBundle {
     fn key(&self) -> &str {
         "InsertOrthographicCameraBundle"
     }
+
+    fn properties(&self) -> &[(&str, &str)] {
+        &[("scale", "f32")]
+    }
 }
 
 /// Inserts a [PerspectiveCameraBundle].
@@ -309,4 +368,8 @@ impl BuildPrefabCommand for InsertPerspectiveCameraBundle {
     fn key(&self) -> &str {
         "InsertPerspectiveCameraBundle"
     }
+
+    fn properties(&self) -> &[(&str, &str)] {
+        &[("position", "Vec3"), ("looking_at", "Vec3")]
+    }
 }
@@ -0,0 +1,236 @@
+use std::{cell::RefCell, sync::Arc};
+
+use crate::{parse::LoadPrefabError, prefab::PrefabParam, registry::PrefabRegistry, ErrorLocation};
+
+/// How serious a [PrefabDiagnostic] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The prefab can still be loaded, but might not behave as expected.
+    Warning,
+    /// The prefab can't be loaded.
+    Error,
+}
+
+/// A problem found in a *.prefab* file by [PrefabRegistry::diagnose].
+#[derive(Debug)]
+pub struct PrefabDiagnostic {
+    pub severity: Severity,
+    /// The problem. Use [LoadPrefabError::kind] to get the error without it's location.
+    pub error: LoadPrefabError,
+}
+
+impl PrefabDiagnostic {
+    pub fn error(error: LoadPrefabError) -> Self {
+        PrefabDiagnostic {
+            severity: Severity::Error,
+            error,
+        }
+    }
+
+    pub fn warning(error: LoadPrefabError) -> Self {
+        PrefabDiagnostic {
+            severity: Severity::Warning,
+            error,
+        }
+    }
+
+    /// Where in the *.prefab* file the problem occurred, if known.
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        self.error.location()
+    }
+}
+
+impl std::fmt::Display for PrefabDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.error),
+            Severity::Error => write!(f, "error: {}", self.error),
+        }
+    }
+}
+
+/// State shared while parsing a prefab.
+pub(crate) struct ParseContext<'a> {
+    pub registry: &'a PrefabRegistry,
+    /// Problems found so far in diagnostic mode, or [None] if parsing stops at the first error.
+    diagnostics: Option<RefCell<Vec<PrefabDiagnostic>>>,
+    /// The prefab being parsed, preceded by the prefabs that inherit from it.
+    chain: Vec<String>,
+    /// The entities referred to by the component being parsed, or [None] outside of
+    /// prefab components.
+    entity_refs: RefCell<Option<Vec<String>>>,
+    /// Entity names given in the prefab, with the error to report if they're duplicated.
+    named_entities: RefCell<Vec<(String, LoadPrefabError)>>,
+    /// Entity references made in the prefab, with the error to report if they're unknown.
+    referenced_entities: RefCell<Vec<(String, LoadPrefabError)>>,
+    /// The parameters declared by the prefab and the prefab it inherits from.
+    params: RefCell<Vec<Arc<PrefabParam>>>,
+    /// The last parameter used since [ParseContext::track_params] was called.
+    used_param: RefCell<Option<String>>,
+}
+
+impl<'a> ParseContext<'a> {
+    pub fn new(registry: &'a PrefabRegistry) -> Self {
+        ParseContext {
+            registry,
+            diagnostics: None,
+            chain: Vec::new(),
+            entity_refs: Default::default(),
+            named_entities: Default::default(),
+            referenced_entities: Default::default(),
+            params: Default::default(),
+            used_param: Default::default(),
+        }
+    }
+
+    /// A context that records errors and keeps parsing where possible.
+    pub fn diagnostic(registry: &'a PrefabRegistry) -> Self {
+        ParseContext {
+            diagnostics: Some(RefCell::new(Vec::new())),
+            ..Self::new(registry)
+        }
+    }
+
+    /// Set the name of the prefab being parsed.
+    pub fn named(mut self, name: &str) -> Self {
+        self.chain = vec![name.to_string()];
+        self
+    }
+
+    /// A context for parsing a base prefab, or an error if the prefab already inherits
+    /// from it. Base prefabs aren't parsed in diagnostic mode, their errors are reported
+    /// on the prefab that inherits from them.
+    pub fn inherit(&self, base: &str) -> Result<ParseContext<'a>, LoadPrefabError> {
+        let mut chain = self.chain.clone();
+        chain.push(base.to_string());
+        if let Some(start) = self.chain.iter().position(|n| n == base) {
+            return Err(LoadPrefabError::Cycle(chain.split_off(start)));
+        }
+        Ok(ParseContext {
+            chain,
+            ..Self::new(self.registry)
+        })
+    }
+
+    /// Start collecting the entity references in a prefab component.
+    pub fn begin_component(&self) {
+        *self.entity_refs.borrow_mut() = Some(Vec::new());
+    }
+
+    /// The entities referred to by the component, in the order of their placeholders.
+    pub fn end_component(&self) -> Vec<String> {
+        self.entity_refs.borrow_mut().take().unwrap_or_default()
+    }
+
+    /// Record the name given to an entity. `error` is reported if the name is used twice.
+    pub fn name_entity(&self, name: &str, error: LoadPrefabError) {
+        self.named_entities
+            .borrow_mut()
+            .push((name.to_string(), error));
+    }
+
+    /// Record a reference to a named entity, returning it's placeholder index in the current
+    /// component. `error` is reported if no entity has the name.
+    pub fn reference_entity(&self, name: &str, error: LoadPrefabError) -> Option<usize> {
+        let mut entity_refs = self.entity_refs.borrow_mut();
+        let entity_refs = entity_refs.as_mut()?;
+        self.referenced_entities
+            .borrow_mut()
+            .push((name.to_string(), error));
+        match entity_refs.iter().position(|n| n == name) {
+            Some(index) => Some(index),
+            None => {
+                entity_refs.push(name.to_string());
+                Some(entity_refs.len() - 1)
+            }
+        }
+    }
+
+    /// Declare a parameter, replacing any parameter with the same name.
+    pub fn declare_param(&self, param: Arc<PrefabParam>) {
+        let mut params = self.params.borrow_mut();
+        params.retain(|p| p.name != param.name);
+        params.push(param);
+    }
+
+    /// The declared parameter with the given name.
+    pub fn param(&self, name: &str) -> Option<Arc<PrefabParam>> {
+        self.params
+            .borrow()
+            .iter()
+            .find(|p| p.name == name)
+            .cloned()
+    }
+
+    /// The declared parameters.
+    pub fn params(&self) -> Vec<Arc<PrefabParam>> {
+        self.params.borrow().clone()
+    }
+
+    /// Start tracking whether a value uses a parameter.
+    pub fn track_params(&self) {
+        self.used_param.take();
+    }
+
+    /// Record that a parameter was used.
+    pub fn use_param(&self, name: &str) {
+        *self.used_param.borrow_mut() = Some(name.to_string());
+    }
+
+    /// The last parameter used since [ParseContext::track_params] was called.
+    pub fn used_param(&self) -> Option<String> {
+        self.used_param.borrow().clone()
+    }
+
+    /// Check the entity names and references recorded so far against the names in the
+    /// parsed prefab.
+    pub fn check_entity_names(&self, names: &[&str]) -> Result<(), LoadPrefabError> {
+        for (name, error) in self.named_entities.take() {
+            if names.iter().filter(|n| **n == name).count() > 1 {
+                self.recover::<()>(Err(error))?;
+            }
+        }
+        for (name, error) in self.referenced_entities.take() {
+            if !names.contains(&name.as_str()) {
+                self.recover::<()>(Err(error))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_diagnostic(&self) -> bool {
+        self.diagnostics.is_some()
+    }
+
+    /// In diagnostic mode errors are recorded and [None] is returned so the caller can
+    /// skip the failed item. Otherwise errors are returned as is.
+    pub fn recover<T>(
+        &self,
+        result: Result<T, LoadPrefabError>,
+    ) -> Result<Option<T>, LoadPrefabError> {
+        match (result, &self.diagnostics) {
+            (Ok(value), _) => Ok(Some(value)),
+            (Err(e), Some(diagnostics)) => {
+                diagnostics.borrow_mut().push(PrefabDiagnostic::error(e));
+                Ok(None)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// The recorded problems, in the order they appear in the prefab.
+    pub fn into_diagnostics(self) -> Vec<PrefabDiagnostic> {
+        let mut diagnostics = self
+            .diagnostics
+            .map(RefCell::into_inner)
+            .unwrap_or_default();
+        // Errors are recorded once their enclosing item fails, so nested errors
+        // can be recorded out of order.
+        diagnostics.sort_by_key(|d| {
+            d.location()
+                .map(|l| (l.line, l.column))
+                .unwrap_or((usize::MAX, usize::MAX))
+        });
+        diagnostics
+    }
+}
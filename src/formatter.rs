@@ -0,0 +1,595 @@
+//! Formats *.prefab* files with a consistent layout, keeping their comments.
+//!
+//! The text is read into a tree of tokens and bracketed groups, with comments attached to
+//! the entries around them, then printed again.
+
+use crate::parse::{check_syntax, LoadPrefabError};
+
+const INDENT: &str = "    ";
+/// Groups are broken into one entry per line when they'd make a line longer than this.
+const MAX_WIDTH: usize = 100;
+
+/// Format the text of a *.prefab* file.
+///
+/// Prefab bodies and `children` lists are written with one entry per line, other brackets
+/// are kept on one line unless they contain a comment or wouldn't fit. Entries written one
+/// per line always end with a comma. Comments and single blank lines between entries are
+/// kept.
+///
+/// Returns an error if the text isn't a valid prefab file. Types aren't checked, so the
+/// file doesn't need to be loadable.
+///
+/// ## Example
+///
+/// ```
+/// use bevy_lazy_prefabs::*;
+///
+/// let input = "Ghost{Visibility{is_visible:false}, // Hidden until it attacks
+/// InsertSpriteBundle!(texture_path:\"ghost.png\")}";
+/// assert_eq!(
+///     format_prefab(input).unwrap(),
+///     "Ghost {
+///     Visibility { is_visible: false }, // Hidden until it attacks
+///     InsertSpriteBundle!(texture_path: \"ghost.png\"),
+/// }
+/// "
+/// );
+/// ```
+pub fn format_prefab(input: &str) -> Result<String, LoadPrefabError> {
+    check_syntax(input)?;
+
+    let tokens = lex(input);
+    let mut pos = 0;
+    let file = parse_group(&tokens, &mut pos, None);
+
+    let mut out = String::new();
+    let mut previous: Option<&Entry> = None;
+    for entry in file.entries.iter() {
+        // Top level items are separated by blank lines, except for consecutive imports.
+        if let Some(previous) = previous {
+            if !(is_import(previous) && is_import(entry)) || entry.blank_before {
+                out.push('\n');
+            }
+        }
+        for comment in entry.leading.iter() {
+            out.push_str(comment);
+            out.push('\n');
+        }
+        out.push_str(&entry_string(entry, Context::TopLevel, 0, false));
+        push_comments(&mut out, &entry.trailing);
+        out.push('\n');
+        previous = Some(entry);
+    }
+    if !file.closing.is_empty() && !file.entries.is_empty() {
+        out.push('\n');
+    }
+    for comment in file.closing.iter() {
+        out.push_str(comment);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TokenKind {
+    /// Identifiers, numbers, strings and chars.
+    Word,
+    Punct,
+    Open,
+    Close,
+    Comment,
+}
+
+#[derive(Debug)]
+struct Token<'a> {
+    kind: TokenKind,
+    text: &'a str,
+    /// The number of line breaks between this token and the last one.
+    newlines: usize,
+}
+
+/// The length of the quoted string or char at the start of `text`, including it's quotes.
+fn quoted_len(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+fn lex(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut newlines = 0;
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            if c == '\n' {
+                newlines += 1;
+            }
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let (kind, len) = if rest.starts_with("//") {
+            (TokenKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if c == '"' || c == '\'' {
+            (TokenKind::Word, quoted_len(rest, c))
+        } else if rest.starts_with("::") || rest.starts_with("..") {
+            (TokenKind::Punct, 2)
+        } else if is_word_start(rest) {
+            (TokenKind::Word, word_len(rest))
+        } else if matches!(c, '{' | '(' | '[') {
+            (TokenKind::Open, 1)
+        } else if matches!(c, '}' | ')' | ']') {
+            (TokenKind::Close, 1)
+        } else {
+            (TokenKind::Punct, c.len_utf8())
+        };
+
+        let (text, remaining) = rest.split_at(len);
+        tokens.push(Token {
+            kind,
+            text: text.trim_end(),
+            newlines,
+        });
+        newlines = 0;
+        rest = remaining;
+    }
+    tokens
+}
+
+fn is_word_start(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some('-') => matches!(chars.next(), Some(c) if c.is_ascii_digit()),
+        Some(c) => c.is_alphanumeric() || c == '_',
+        None => false,
+    }
+}
+
+/// The length of an identifier or number, ie: `-1.5e-7f64`.
+fn word_len(text: &str) -> usize {
+    let is_number = !text.starts_with(|c: char| c.is_alphabetic() || c == '_');
+    let bytes = text.as_bytes();
+    let word_end = |start: usize| {
+        text[start..]
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(text.len(), |i| i + start)
+    };
+
+    let mut len = word_end(1);
+    if is_number && text[len..].starts_with('.') && !text[len..].starts_with("..") {
+        len = word_end(len + 1);
+    }
+    if is_number
+        && matches!(bytes[len - 1], b'e' | b'E')
+        && matches!(bytes.get(len), Some(b'+' | b'-'))
+    {
+        len = word_end(len + 1);
+    }
+    len
+}
+
+#[derive(Debug)]
+enum Tree<'a> {
+    Token(&'a str),
+    Group(Group<'a>),
+}
+
+/// A bracketed group, or the whole file.
+#[derive(Debug, Default)]
+struct Group<'a> {
+    open: &'a str,
+    close: &'a str,
+    /// Comments on the same line as the opening bracket.
+    comments: Vec<&'a str>,
+    entries: Vec<Entry<'a>>,
+    /// Comments on their own lines after the last entry.
+    closing: Vec<&'a str>,
+}
+
+/// An item in a group, ie: a field, component or prefab.
+#[derive(Debug, Default)]
+struct Entry<'a> {
+    /// Comments on their own lines before the entry.
+    leading: Vec<&'a str>,
+    items: Vec<Tree<'a>>,
+    /// Comments on the same line as the entry, or inside it.
+    trailing: Vec<&'a str>,
+    blank_before: bool,
+}
+
+/// Read the entries of a group up to it's closing bracket. Entries in a group are separated
+/// by commas, top level items end after their body or, for imports, their file name.
+fn parse_group<'a>(tokens: &[Token<'a>], pos: &mut usize, open: Option<&'a str>) -> Group<'a> {
+    let mut group = Group {
+        open: open.unwrap_or_default(),
+        ..Default::default()
+    };
+    let top_level = open.is_none();
+    let mut entry = Entry::default();
+    // Comments on their own lines after part of an entry, which belong to the entry unless
+    // they're at the end of the group.
+    let mut pending = Vec::new();
+    let mut after_open = true;
+    let mut after_entry = false;
+    let mut angle_depth = 0;
+
+    while let Some(token) = tokens.get(*pos) {
+        *pos += 1;
+        match token.kind {
+            TokenKind::Comment => {
+                let own_line = token.newlines > 0 || *pos == 1;
+                if !own_line && after_open {
+                    group.comments.push(token.text);
+                } else if !own_line && after_entry {
+                    group.entries.last_mut().unwrap().trailing.push(token.text);
+                } else if top_level && !entry.items.is_empty() {
+                    // Comments in a prefab's header are kept above it, rather than moving
+                    // after it's body.
+                    entry.leading.push(token.text);
+                } else if own_line && !entry.items.is_empty() {
+                    pending.push(token.text);
+                } else if entry.items.is_empty() {
+                    if entry.leading.is_empty() {
+                        entry.blank_before = token.newlines > 1;
+                    }
+                    entry.leading.push(token.text);
+                } else {
+                    entry.trailing.push(token.text);
+                }
+                continue;
+            }
+            TokenKind::Close => {
+                group.close = token.text;
+                break;
+            }
+            _ => entry.trailing.append(&mut pending),
+        }
+        match token.kind {
+            TokenKind::Punct if token.text == "," && angle_depth == 0 => {
+                group.entries.push(std::mem::take(&mut entry));
+                after_open = false;
+                after_entry = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        if entry.items.is_empty() && entry.leading.is_empty() {
+            entry.blank_before = token.newlines > 1;
+        }
+        match token.kind {
+            TokenKind::Open => {
+                let inner = parse_group(tokens, pos, Some(token.text));
+                entry.items.push(Tree::Group(inner));
+            }
+            _ => {
+                match token.text {
+                    "<" => angle_depth += 1,
+                    ">" => angle_depth -= 1,
+                    _ => {}
+                }
+                entry.items.push(Tree::Token(token.text));
+            }
+        }
+        after_open = false;
+        after_entry = false;
+
+        let item_end = match entry.items.last() {
+            Some(Tree::Group(group)) => group.open == "{",
+            _ => is_import(&entry),
+        };
+        if top_level && item_end {
+            group.entries.push(std::mem::take(&mut entry));
+            after_entry = true;
+        }
+    }
+
+    if entry.items.is_empty() {
+        group.closing = entry.leading;
+    } else {
+        group.entries.push(entry);
+        group.closing = pending;
+    }
+    group
+}
+
+fn is_import(entry: &Entry) -> bool {
+    matches!(
+        entry.items.as_slice(),
+        [Tree::Token("import"), Tree::Token(file)] if file.starts_with('"')
+    )
+}
+
+fn has_comments(group: &Group) -> bool {
+    !group.comments.is_empty()
+        || !group.closing.is_empty()
+        || group.entries.iter().any(|entry| {
+            !entry.leading.is_empty()
+                || !entry.trailing.is_empty()
+                || entry.items.iter().any(|item| match item {
+                    Tree::Group(group) => has_comments(group),
+                    Tree::Token(_) => false,
+                })
+        })
+}
+
+/// What the entries of a group are, which decides how they're laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    /// Prefabs, component groups and imports.
+    TopLevel,
+    /// The components, commands and children of a prefab.
+    PrefabFields,
+    /// The child prefabs in a `children` list.
+    Children,
+    Other,
+}
+
+/// An entry without it's comments or trailing comma. `indent` is the indentation of the
+/// line the entry starts on.
+fn entry_string(entry: &Entry, context: Context, indent: usize, inline: bool) -> String {
+    let mut out = String::new();
+
+    // Prefab headers are written with spaces between each part, ie: `Orc #orc : "base.prefab"`.
+    let is_prefab = matches!(context, Context::TopLevel | Context::Children);
+    if let (true, Some((Tree::Group(body), header))) = (is_prefab, entry.items.split_last()) {
+        if body.open == "{" {
+            for item in header {
+                if let Tree::Token(text) = item {
+                    out.push_str(text);
+                    if *text != "#" {
+                        out.push(' ');
+                    }
+                }
+            }
+            out.push_str(&group_string(
+                body,
+                Context::PrefabFields,
+                true,
+                indent,
+                column(&out, indent),
+            ));
+            return out;
+        }
+    }
+
+    let mut previous = None;
+    for item in entry.items.iter() {
+        if let Some(previous) = previous {
+            if space_between(previous, item) {
+                out.push(' ');
+            }
+        }
+        match item {
+            Tree::Token(text) => out.push_str(text),
+            Tree::Group(group) => {
+                let is_children = context == Context::PrefabFields
+                    && group.open == "["
+                    && matches!(entry.items.first(), Some(Tree::Token("children")));
+                let (inner, block) = match is_children {
+                    true => (Context::Children, true),
+                    false => (Context::Other, false),
+                };
+                let text = match inline {
+                    true => inline_string(group, inner, indent),
+                    false => group_string(group, inner, block, indent, column(&out, indent)),
+                };
+                out.push_str(&text);
+            }
+        }
+        previous = Some(item);
+    }
+    out
+}
+
+/// The column the next character of `text` will be written at.
+fn column(text: &str, indent: usize) -> usize {
+    match text.rfind('\n') {
+        Some(i) => text.len() - i - 1,
+        None => indent * INDENT.len() + text.len(),
+    }
+}
+
+fn space_between(previous: &Tree, next: &Tree) -> bool {
+    match (previous, next) {
+        (Tree::Token("#" | "@" | "$" | "::" | "!" | "<" | ".."), _) => false,
+        (_, Tree::Token("," | ":" | "::" | "!" | "<" | ">" | ".." | ";")) => false,
+        (Tree::Token(":" | "," | "=" | ";"), _) => true,
+        (_, Tree::Group(group)) => group.open != "(",
+        _ => true,
+    }
+}
+
+/// A group written on one line, ie: `{ x: 1.0, y: 2.0 }`.
+fn inline_string(group: &Group, context: Context, indent: usize) -> String {
+    let entries: Vec<_> = group
+        .entries
+        .iter()
+        .map(|entry| entry_string(entry, context, indent, true))
+        .collect();
+    match (group.open, entries.is_empty()) {
+        (_, true) => format!("{}{}", group.open, group.close),
+        ("{", false) => format!("{{ {} }}", entries.join(", ")),
+        (open, false) => format!("{}{}{}", open, entries.join(", "), group.close),
+    }
+}
+
+/// A group written on one line if it fits and has no comments, otherwise with one entry per
+/// line. `column` is where the group starts.
+fn group_string(
+    group: &Group,
+    context: Context,
+    block: bool,
+    indent: usize,
+    column: usize,
+) -> String {
+    if !block && !has_comments(group) {
+        let inline = inline_string(group, context, indent);
+        // Leave room for the comma or bracket after the group.
+        if column + inline.len() < MAX_WIDTH {
+            return inline;
+        }
+    }
+    if group.entries.is_empty() && !has_comments(group) {
+        return format!("{}{}", group.open, group.close);
+    }
+
+    let mut out = group.open.to_string();
+    push_comments(&mut out, &group.comments);
+    out.push('\n');
+
+    let inner = INDENT.repeat(indent + 1);
+    for (i, entry) in group.entries.iter().enumerate() {
+        if i > 0 && entry.blank_before {
+            out.push('\n');
+        }
+        for comment in entry.leading.iter() {
+            out.push_str(&inner);
+            out.push_str(comment);
+            out.push('\n');
+        }
+        out.push_str(&inner);
+        out.push_str(&entry_string(entry, context, indent + 1, false));
+        // Ranges and array types can't have a trailing comma.
+        let single = group.open != "{" && group.entries.len() == 1;
+        if !(single
+            && entry
+                .items
+                .iter()
+                .any(|item| matches!(item, Tree::Token(".." | ";"))))
+        {
+            out.push(',');
+        }
+        push_comments(&mut out, &entry.trailing);
+        out.push('\n');
+    }
+    for comment in group.closing.iter() {
+        out.push_str(&inner);
+        out.push_str(comment);
+        out.push('\n');
+    }
+    out.push_str(&INDENT.repeat(indent));
+    out.push_str(group.close);
+    out
+}
+
+fn push_comments(out: &mut String, comments: &[&str]) {
+    if !comments.is_empty() {
+        out.push(' ');
+        out.push_str(&comments.join(" "));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::format_prefab;
+
+    #[test]
+    fn format_layout() {
+        let input = "import \"a.prefab\"
+import \"b.prefab\"
+group Armored{Armor(5)}
+Knight#knight:\"base.prefab\"{params{hp:i32=10,},
+  ..Armored,Unit{name:\"S\\\" r\",ids:[$hp,-2],range:(0..5),map:HashMap<String, i32>{\"a\":1}},
+  InsertSpriteBundle!(),
+  children:[#horse{Transform{translation:Vec3{y:1.5e-7}}}],
+}";
+        let expected = "import \"a.prefab\"
+import \"b.prefab\"
+
+group Armored {
+    Armor(5),
+}
+
+Knight #knight : \"base.prefab\" {
+    params { hp: i32 = 10 },
+    ..Armored,
+    Unit { name: \"S\\\" r\", ids: [$hp, -2], range: (0..5), map: HashMap<String, i32> { \"a\": 1 } },
+    InsertSpriteBundle!(),
+    children: [
+        #horse {
+            Transform { translation: Vec3 { y: 1.5e-7 } },
+        },
+    ],
+}
+";
+        let formatted = format_prefab(input).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_prefab(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn format_comments() {
+        let input = "// The player
+Player {  // Name is optional
+
+    // Components
+    Transform { translation: Vec3 { x: 15.0 }},  // Start position
+    InsertSpriteBundle!(
+        texture_path: \"alien.png\",    // The texture
+        color: Color::RED
+    )
+    // The end
+}
+Enemy // Header
+// More header
+{ Visibility }
+// Trailing comment";
+        let expected = "// The player
+Player { // Name is optional
+    // Components
+    Transform { translation: Vec3 { x: 15.0 } }, // Start position
+    InsertSpriteBundle!(
+        texture_path: \"alien.png\", // The texture
+        color: Color::RED,
+    ),
+    // The end
+}
+
+// Header
+// More header
+Enemy {
+    Visibility,
+}
+
+// Trailing comment
+";
+        let formatted = format_prefab(input).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_prefab(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn format_long_lines() {
+        let input = "{ InsertPerspectiveCameraBundle!(position: Vec3 { x: 2.5, z: 10.0, y: 1.5 }, looking_at: Vec3 { x: 1.0 }) }";
+        let expected = "{
+    InsertPerspectiveCameraBundle!(
+        position: Vec3 { x: 2.5, z: 10.0, y: 1.5 },
+        looking_at: Vec3 { x: 1.0 },
+    ),
+}
+";
+        assert_eq!(format_prefab(input).unwrap(), expected);
+        assert!(format_prefab("{ Visibility ").is_err());
+    }
+
+    #[test]
+    fn format_assets() {
+        for entry in std::fs::read_dir("assets").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("prefab") {
+                continue;
+            }
+            let input = std::fs::read_to_string(&path).unwrap();
+            let formatted = format_prefab(&input).unwrap();
+            assert_eq!(format_prefab(&formatted).unwrap(), formatted, "{:?}", path);
+        }
+    }
+}
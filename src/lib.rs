@@ -26,6 +26,11 @@
 //!
 //! In the above example we are authoring a prefab with `Transform`, `Visibility`, and `SomeComponent` components.
 //! In this case the entity's transform will be initialized to position (15.0,10.0,0.0) when entity is spawned.
+//! Values are parsed as the type of the field they're assigned to, so `some_int: 15` works for any numeric
+//! field type. Values that don't fit the field's type are reported as errors when the prefab is loaded.
+//! Numbers can be written with digit separators (`10_000`), in hex or binary (`0xFF`, `0b101`) or with an
+//! explicit type suffix (`5u8`, `2.0f64`). Booleans are written as `true` or `false`. Strings and chars can
+//! contain the escapes `\"`, `\'`, `\\`, `\n`, `\t` and `\r`, ie: `"Sir \"Lance\""`.
 //!
 //! Custom components will only work in prefabs if they derive `Reflect` and `Default`, and if they have the
 //! `#[reflect(Component)]` attribute. Most built in bevy types already meet this constraint. They must also be
@@ -42,7 +47,8 @@
 //! Custom commands can be authored, but there are several included for more common components:
 //! - `InsertSpriteBundle` - Inserts a `SpriteBundle` on an entity. Can specify `color` and `texture_path`.
 //! - `SetColorMaterial` - Modify an existing `ColorMaterial` on the entity.
-//! - `LoadPrefab` - Load an existing prefab and perform it's build steps on the current entity.
+//! - `LoadPrefab` - Load an existing prefab and perform it's build steps on the current entity. Prefabs that load
+//!   each other in a cycle fail to load.
 //! - `InsertPbrBundle` - Inserts a `PbrBundle`. Can specify mesh `shape`, `size`, and `flip`.
 //! - `InsertOrthographicCameraBundle` - Inserts an `OrthographicCameraBundle`. Can specify `scale`.
 //! - `InsertPerspectiveCameraBundle` - Inserts a `PerspectiveCameraBundle`. Can specify `position` and `looking_at`.
@@ -66,6 +72,142 @@
 //! to the build command and used in the initialization process. How these properties get used is defined by every
 //! individual build command.
 //!
+//! # Enums
+//!
+//! Enum components and fields are written as `Type::Variant`, followed by the variant's fields if it has any.
+//! `Option` fields can be written as `Some(value)` or `None`.
+//! ```ignore
+//! {
+//!     Faction::Enemy(3),                     // Enum components are written as a variant
+//!     Unit {
+//!         state: State::Moving { speed: 2.0 }, // Struct variants use braces
+//!         target: Some(5),
+//!     },
+//! }
+//! ```
+//!
+//! When changing an enum's variant every field of the new variant must be given.
+//!
+//! # Collections And Values
+//!
+//! Registered list, array and map types are written with their values in square brackets or as key/value pairs, and
+//! value types with their value in parentheses.
+//! ```ignore
+//! {
+//!     Vec<i32> [1, 2, 3],
+//!     HashMap<String, i32> { "sword": 1, "shield": 2 },
+//!     String("Polly"),
+//! }
+//! ```
+//!
+//! # Inheritance
+//!
+//! A prefab can inherit from another prefab by naming the base prefab's file after it's name. The base prefab's components
+//! and build commands are included in the prefab, and components given again are merged into the base prefab's
+//! components field by field. Nested components, tuple structs, maps and enums of the same variant are merged the same
+//! way, other values replace the base prefab's value.
+//! ```ignore
+//! SharpSword : "sword.prefab" {   // The prefab name is still optional, ie: `: "sword.prefab" { ... }`
+//!     DealsDamage { value: 10 },  // Only `value` changes, other fields keep the values from sword.prefab
+//! }
+//! ```
+//!
+//! Inheritance is resolved when the prefab is loaded, so the loaded prefab is no more expensive to spawn than if it was
+//! written out in full.
+//!
+//! # Child Entities
+//!
+//! Child entities are listed in `children`, written the same way as a prefab. They're spawned with `Parent` and
+//! `Children` set up when the prefab is inserted, and can have their own components, build commands and children.
+//! ```ignore
+//! Tank {
+//!     Transform, GlobalTransform,
+//!     children: [
+//!         Turret {                          // Child prefab names are optional too
+//!             Transform { translation: Vec3 { y: 1.0 } },
+//!             GlobalTransform,
+//!         },
+//!         : "tread.prefab" { Visibility },  // Children can inherit from other prefabs
+//!     ],
+//! }
+//! ```
+//!
+//! When a prefab is reloaded it's children are despawned and spawned again.
+//!
+//! # Entity References
+//!
+//! Entities in a prefab can be named with `#name`, after the prefab's name if it has one. Fields of type `Entity` in
+//! the prefab's components can then refer to them with `@name`, and are set to the spawned entity when the prefab is
+//! inserted.
+//! ```ignore
+//! Tank #tank {
+//!     Turret { muzzle: @muzzle },
+//!     children: [
+//!         #muzzle { Transform { translation: Vec3 { y: 2.0 } } },
+//!     ],
+//! }
+//! ```
+//!
+//! Names are only visible within the prefab they're given in, including it's children and any prefab it inherits from.
+//! Prefabs loaded with `LoadPrefab` have their own names.
+//!
+//! # Parameters
+//!
+//! A prefab can declare parameters in a `params` block at the start of the prefab. Each parameter has a type and a
+//! default value, and can be used with `$name` in place of any value of the same type, including in child prefabs and
+//! build command properties.
+//! ```ignore
+//! Goblin {
+//!     params { hp: i32 = 10, tint: Color = Color::GREEN },
+//!     Health { max: $hp, current: $hp },
+//!     InsertSpriteBundle!(texture_path: "goblin.png", color: $tint),
+//! }
+//! ```
+//!
+//! Values for the parameters are given when the prefab is inserted with `insert_prefab_with`:
+//! ```ignore
+//! let mut args = DynamicStruct::default();
+//! args.insert("hp", 20);
+//! commands.spawn_empty().insert_prefab_with(goblin, args);
+//! ```
+//!
+//! Parameters can't be used inside `Vec2`, `Vec3`, `Quat` or `Color` values. Prefabs that inherit from a prefab with parameters
+//! can use it's parameters, and can declare them again to change their default value.
+//!
+//! # Multiple Prefabs
+//!
+//! A file can hold several prefabs, one after the other. Named prefabs in the file are loaded by adding their name to
+//! the file name after a `#`, and the file name alone refers to the first prefab in the file.
+//! ```ignore
+//! Goblin { Health { max: 10 } }
+//! Orc { Health { max: 30 } }
+//! ```
+//! ```ignore
+//! let orc = registry.load("enemies.prefab#Orc").unwrap();
+//! ```
+//!
+//! The same names work with `LoadPrefab`, inheritance and the `AssetServer`. Prefab names must be unique within a file.
+//!
+//! # Component Groups
+//!
+//! Components and build commands that are repeated across prefabs can be declared once in a `group`, and spliced into
+//! any prefab in the same file with `..Name`. Groups in other files can be used after importing the file with `import`
+//! at the start of the file.
+//! ```ignore
+//! // common.prefab
+//! group Damageable { Health { max: 10 }, Hitbox }
+//! ```
+//! ```ignore
+//! import "common.prefab"
+//!
+//! Goblin {
+//!     ..Damageable,
+//!     Health { max: 5 },  // Merged into the group's Health component, like with inheritance
+//! }
+//! ```
+//!
+//! Groups are resolved when the prefab is loaded. Groups declared in a file replace imported groups with the same name.
+//!
 //! # Spawning A Prefab
 //!
 //! Once you have your *.prefab* file in the *assets/* directory you can spawn a prefab using the
@@ -82,17 +224,130 @@
 //!   commands.spawn_empty().insert_prefab(cam);
 //!  }
 //! ```
+//!
+//! Prefabs can also be loaded through the `AssetServer`. A prefab inserted from a `Handle<Prefab>` is applied once it
+//! and any prefabs it loads with `LoadPrefab` have finished loading:
+//!
+//! ```
+//! use bevy::prelude::*;
+//! use bevy_lazy_prefabs::*;
+//!
+//! fn setup(mut commands: Commands, server: Res<AssetServer>) {
+//!   let sprite: Handle<Prefab> = server.load("sprite.prefab");
+//!   commands.spawn_empty().insert_prefab(sprite);
+//! }
+//! ```
+//!
+//! Types and build commands must be registered before a prefab is loaded.
+//!
+//! # Prefab Sources
+//!
+//! By default the `PrefabRegistry` reads prefabs from the *assets/* folder. Other folders, prefabs stored in memory or
+//! custom readers can be added with `PrefabRegistry::add_source`. Sources added later override prefabs with the same name
+//! in earlier sources.
+//!
+//! Prefabs can also be parsed from text with `PrefabRegistry::load_from_str`, or built in code with a `PrefabBuilder` and
+//! added to the registry with `PrefabRegistry::add_prefab`.
+//!
+//! `Prefab::to_prefab_string` writes a prefab back out as *.prefab* text, with inheritance and component groups
+//! already resolved. Parsing the text gives an equivalent prefab. Values that can't be written in a prefab, such as
+//! entities outside the prefab or types with no prefab syntax, are returned as a `WritePrefabError`.
+//!
+//! `PrefabRegistry::entity_to_prefab` builds a prefab from an entity in a `World`, including it's registered components
+//! and children, so changes made while the game is running can be saved as a *.prefab* file.
+//!
+//! # Formatting
+//!
+//! `format_prefab` formats the text of a *.prefab* file with a consistent layout, keeping it's comments. The `prefab-fmt`
+//! binary formats files or directories of prefabs in place, or lists the unformatted files and exits with an error when
+//! given `--check`:
+//!
+//! ```text
+//! cargo run --bin prefab-fmt -- --check assets
+//! ```
+//!
+//! # Checking Prefabs
+//!
+//! The `prefab-check` binary checks prefab files outside of the game, ie: in CI. The game's registered types and build
+//! commands are described by a JSON `TypeManifest`, which is added to a registry with
+//! `PrefabRegistry::register_manifest`. Prefabs are then checked by `PrefabRegistry::diagnose` the same way the game
+//! would check them, and every error is reported with it's location:
+//!
+//! ```text
+//! cargo run --bin prefab-check -- --manifest types.json assets
+//! ```
+//!
+//! ```json
+//! {
+//!     "types": [
+//!         {
+//!             "name": "Health",
+//!             "component": true,
+//!             "kind": "struct",
+//!             "fields": [{ "name": "max", "type": "i32" }]
+//!         },
+//!         {
+//!             "name": "Team",
+//!             "component": true,
+//!             "kind": "enum",
+//!             "variants": [
+//!                 { "name": "Red", "kind": "unit" },
+//!                 { "name": "Custom", "kind": "tuple", "fields": ["Color"] }
+//!             ]
+//!         }
+//!     ],
+//!     "commands": [
+//!         {
+//!             "name": "InsertSpriteBundle",
+//!             "properties": [{ "name": "texture_path", "type": "String" }]
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! The manifest can be written from the game's registry with `PrefabRegistry::manifest` and `TypeManifest::to_json`.
+//! It lists the fields and default values of every registered type, and the properties declared by each build
+//! command's `BuildPrefabCommand::properties`. Tools read it back with `TypeManifest::from_json` or
+//! `TypeManifest::from_file`.
+//!
+//! # Hot Reloading
+//!
+//! Call `PrefabRegistry::watch_for_changes` to reload prefab files when they change. Reloaded prefabs are applied again to
+//! every entity they were inserted into, including entities with prefabs that inherit from them or load them with
+//! `LoadPrefab`, and a `PrefabReloaded` event is sent for each one. Prefabs loaded through the `AssetServer` are reloaded
+//! the same way when the `AssetPlugin` is watching for changes.
+//!
+//! Components that were removed from a prefab file are not removed from existing entities.
 
 mod bevy_commands;
+mod diagnostic;
+mod formatter;
+mod loader;
+mod manifest;
 mod parse;
 mod plugin;
 mod prefab;
 mod registry;
+mod reload;
+mod snapshot;
+mod source;
+mod value_type;
+mod writer;
 
 pub mod build_commands;
 pub mod dynamic_cast;
 
-pub use bevy_commands::SpawnPrefabCommands;
+pub use bevy_commands::{InsertPrefab, SpawnPrefabCommands};
+pub use diagnostic::{PrefabDiagnostic, Severity};
+pub use formatter::format_prefab;
+pub use manifest::{
+    ManifestCommand, ManifestError, ManifestField, ManifestKind, ManifestProperty, ManifestType,
+    ManifestVariant, ManifestVariantKind, TypeManifest,
+};
+pub use parse::{ErrorLocation, LoadPrefabError};
 pub use plugin::LazyPrefabsPlugin;
-pub use prefab::Prefab;
+pub use prefab::{Prefab, PrefabBuilder};
 pub use registry::PrefabRegistry;
+pub use reload::{PrefabInstance, PrefabReloaded};
+pub use source::{FilePrefabSource, MemoryPrefabSource, PrefabSource};
+pub use writer::WritePrefabError;
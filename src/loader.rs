@@ -0,0 +1,94 @@
+use std::sync::{Arc, RwLock};
+
+use bevy::{
+    asset::{AssetIoError, AssetLoader, AssetPath, BoxedFuture, LoadContext, LoadedAsset},
+    utils::HashMap,
+};
+
+use crate::{
+    parse::{parse_prefab_file_all, LoadPrefabError},
+    prefab::Prefab,
+    PrefabRegistry,
+};
+
+/// Loads *.prefab* files as [Prefab](crate::Prefab) assets.
+///
+/// Asset loaders run outside of the ECS, so prefabs are parsed with a copy of the
+/// [PrefabRegistry]'s types and build commands, which the registry updates whenever one is
+/// registered.
+#[derive(Default)]
+pub(crate) struct PrefabAssetLoader {
+    registry: Arc<RwLock<PrefabRegistry>>,
+}
+
+impl PrefabAssetLoader {
+    /// The registry the loader parses prefabs with.
+    pub fn registry(&self) -> Arc<RwLock<PrefabRegistry>> {
+        self.registry.clone()
+    }
+}
+
+impl AssetLoader for PrefabAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let name = load_context.path().to_string_lossy().replace('\\', "/");
+            let prefab_string = String::from_utf8_lossy(bytes);
+
+            // Base prefabs and imports are read through the asset server, like the prefab
+            // itself. The file is parsed again whenever one hasn't been read yet.
+            let files: Arc<RwLock<HashMap<String, String>>> = Default::default();
+            let mut registry = self.registry.read().unwrap().clone_types();
+            registry.clear_sources();
+            let read = files.clone();
+            registry.add_source(move |file: &str| {
+                let files = read.read().unwrap();
+                let text = files.get(file).cloned();
+                text.ok_or_else(|| AssetIoError::NotFound(file.into()))
+            });
+
+            // The first prefab in the file is the default asset, and every named prefab is
+            // a labeled asset, ie: `enemies.prefab#Orc`.
+            let (prefab, labeled) = loop {
+                let error = match parse_prefab_file_all(&name, &prefab_string, &registry) {
+                    Ok(prefabs) => break prefabs,
+                    Err(error) => error,
+                };
+                let file = match error.kind() {
+                    LoadPrefabError::FileReadError(file, AssetIoError::NotFound(_)) => file.clone(),
+                    _ => return Err(error.into()),
+                };
+                let bytes = load_context
+                    .read_asset_bytes(&file)
+                    .await
+                    .map_err(|e| LoadPrefabError::FileReadError(file.clone(), e))?;
+                let text = String::from_utf8_lossy(&bytes).into_owned();
+                files.write().unwrap().insert(file, text);
+            };
+
+            load_context.set_default_asset(prefab_asset(prefab));
+            for (label, prefab) in labeled {
+                load_context.set_labeled_asset(&label, prefab_asset(prefab));
+            }
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["prefab"]
+    }
+}
+
+/// A prefab asset, depending on the prefabs it inherits from or loads with `LoadPrefab` so
+/// they're loaded along with it.
+fn prefab_asset(prefab: Prefab) -> LoadedAsset<Prefab> {
+    let dependencies = prefab.dependencies();
+    let mut asset = LoadedAsset::new(prefab);
+    for dependency in dependencies {
+        asset = asset.with_dependency(AssetPath::from(dependency.as_str()).to_owned());
+    }
+    asset
+}
@@ -0,0 +1,445 @@
+//! Describes the game's types, so *.prefab* files can be checked without the types
+//! themselves.
+
+use std::{fs, io, path::Path};
+
+use bevy::{
+    prelude::*,
+    reflect::{ReflectRef, TypeInfo as ReflectTypeInfo, VariantInfo},
+    utils::get_short_name,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{registry::TypeInfo, writer::value_string};
+
+/// The types and build commands a game registers with it's [crate::PrefabRegistry], read from
+/// JSON. Used by the `prefab-check` binary to check prefabs outside of the game.
+///
+/// A game can write it's manifest with [crate::PrefabRegistry::manifest] and
+/// [TypeManifest::to_json]. Default values are written as they would be in a prefab. Prefabs
+/// are checked against a manifest by a registry the manifest was added to with
+/// [crate::PrefabRegistry::register_manifest].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeManifest {
+    #[serde(default)]
+    pub types: Vec<ManifestType>,
+    #[serde(default)]
+    pub commands: Vec<ManifestCommand>,
+}
+
+/// Errors that can occur when reading a [TypeManifest].
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("Error reading type manifest '{0}'.")]
+    ReadError(String, #[source] io::Error),
+    #[error("Error reading type manifest - {0}.")]
+    InvalidJson(#[source] serde_json::Error),
+}
+
+/// A registered type in a [TypeManifest].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestType {
+    /// The name the type is written with in prefabs, ie: `Transform`.
+    pub name: String,
+    /// Whether the type can be added to entities, ie: it has `#[reflect(Component)]`.
+    #[serde(default)]
+    pub component: bool,
+    #[serde(flatten)]
+    pub kind: ManifestKind,
+    /// The type's default value, for types that aren't structs. The fields of structs have
+    /// their own defaults. [None] if the value can't be written in a prefab.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+/// The kind of a [ManifestType], written as it's `kind` field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ManifestKind {
+    Struct {
+        #[serde(default)]
+        fields: Vec<ManifestField>,
+    },
+    /// A tuple struct, with the types of it's fields.
+    TupleStruct {
+        #[serde(default)]
+        fields: Vec<String>,
+    },
+    Tuple {
+        #[serde(default)]
+        fields: Vec<String>,
+    },
+    List,
+    Array,
+    Map,
+    Enum {
+        variants: Vec<ManifestVariant>,
+    },
+    Value,
+}
+
+/// A named field of a struct or enum variant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestField {
+    pub name: String,
+    /// The field's type name, ie: `Vec3`.
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// The field's value in the default value of it's struct, ie: `Vec3 { x: 0.0, y: 0.0, z: 0.0 }`.
+    /// [None] if the value can't be written in a prefab.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+/// A registered build command in a [TypeManifest].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestCommand {
+    pub name: String,
+    /// The properties the command reads. Commands without any declared properties accept
+    /// any properties.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<ManifestProperty>,
+}
+
+/// A property of a build command. See [crate::build_commands::BuildPrefabCommand::properties].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestProperty {
+    pub name: String,
+    /// The property's type name, ie: `f32`, or the type names it accepts separated by `|`.
+    #[serde(rename = "type")]
+    pub type_name: String,
+}
+
+/// A variant of an enum in a [TypeManifest].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestVariant {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ManifestVariantKind,
+}
+
+/// The kind of a [ManifestVariant], written as it's `kind` field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ManifestVariantKind {
+    Unit,
+    /// A tuple variant, with the types of it's fields.
+    Tuple {
+        #[serde(default)]
+        fields: Vec<String>,
+    },
+    Struct {
+        #[serde(default)]
+        fields: Vec<ManifestField>,
+    },
+}
+
+impl TypeManifest {
+    /// Read a manifest from JSON.
+    pub fn from_json(json: &str) -> Result<Self, ManifestError> {
+        serde_json::from_str(json).map_err(ManifestError::InvalidJson)
+    }
+
+    /// Read a manifest from a JSON file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ManifestError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|e| ManifestError::ReadError(path.display().to_string(), e))?;
+        Self::from_json(&json)
+    }
+
+    /// Write the manifest as JSON, to be read with [TypeManifest::from_json].
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// The type with the given name, as it's written in a prefab.
+    pub fn get_type(&self, name: &str) -> Option<&ManifestType> {
+        let name: String = name.split_whitespace().collect();
+        self.types
+            .iter()
+            .find(|t| t.name.split_whitespace().collect::<String>() == name)
+    }
+}
+
+/// Describe a registered type. See [crate::PrefabRegistry::manifest].
+pub(crate) fn manifest_type(info: &TypeInfo) -> ManifestType {
+    let default = &*info.default_value;
+    let field = |name: &str, type_name: &str, default: Option<&dyn Reflect>| ManifestField {
+        name: name.to_string(),
+        type_name: get_short_name(type_name),
+        default: default.and_then(|value| value_string(value, &[]).ok()),
+    };
+
+    let kind = match info.registration.type_info() {
+        ReflectTypeInfo::Struct(info) => {
+            let default = match default.reflect_ref() {
+                ReflectRef::Struct(default) => Some(default),
+                _ => None,
+            };
+            let fields = info
+                .iter()
+                .map(|f| {
+                    field(
+                        f.name(),
+                        f.type_name(),
+                        default.and_then(|d| d.field(f.name())),
+                    )
+                })
+                .collect();
+            ManifestKind::Struct { fields }
+        }
+        ReflectTypeInfo::TupleStruct(info) => ManifestKind::TupleStruct {
+            fields: info.iter().map(|f| get_short_name(f.type_name())).collect(),
+        },
+        ReflectTypeInfo::Tuple(info) => ManifestKind::Tuple {
+            fields: info.iter().map(|f| get_short_name(f.type_name())).collect(),
+        },
+        ReflectTypeInfo::List(_) => ManifestKind::List,
+        ReflectTypeInfo::Array(_) => ManifestKind::Array,
+        ReflectTypeInfo::Map(_) => ManifestKind::Map,
+        ReflectTypeInfo::Enum(info) => ManifestKind::Enum {
+            variants: info
+                .iter()
+                .map(|variant| ManifestVariant {
+                    name: variant.name().to_string(),
+                    kind: match variant {
+                        VariantInfo::Unit(_) => ManifestVariantKind::Unit,
+                        VariantInfo::Tuple(variant) => ManifestVariantKind::Tuple {
+                            fields: variant
+                                .iter()
+                                .map(|f| get_short_name(f.type_name()))
+                                .collect(),
+                        },
+                        VariantInfo::Struct(variant) => ManifestVariantKind::Struct {
+                            fields: variant
+                                .iter()
+                                .map(|f| field(f.name(), f.type_name(), None))
+                                .collect(),
+                        },
+                    },
+                })
+                .collect(),
+        },
+        ReflectTypeInfo::Value(_) | ReflectTypeInfo::Dynamic(_) => ManifestKind::Value,
+    };
+
+    ManifestType {
+        name: info.type_name.clone(),
+        component: info.registration.data::<ReflectComponent>().is_some(),
+        default: match kind {
+            ManifestKind::Struct { .. } => None,
+            _ => value_string(default, &[]).ok(),
+        },
+        kind,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use crate::{
+        build_commands::InsertSpriteBundle, LoadPrefabError, MemoryPrefabSource, PrefabDiagnostic,
+        PrefabRegistry,
+    };
+
+    use super::{ManifestKind, ManifestVariantKind, TypeManifest};
+
+    #[derive(Component, Reflect, Default)]
+    #[reflect(Component)]
+    enum Mood {
+        #[default]
+        Calm,
+        Angry {
+            level: u8,
+        },
+        Bored(u8, String),
+    }
+
+    const MANIFEST: &str = r#"{
+        "types": [
+            { "name": "Transform", "component": true, "kind": "struct", "fields": [
+                { "name": "translation", "type": "Vec3" },
+                { "name": "rotation", "type": "Quat" },
+                { "name": "scale", "type": "Vec3" }
+            ] },
+            { "name": "Stats", "kind": "struct", "fields": [{ "name": "level", "type": "u8" }] },
+            { "name": "Unit", "component": true, "kind": "struct", "fields": [
+                { "name": "stats", "type": "Stats" }
+            ] },
+            { "name": "Team", "component": true, "kind": "enum", "variants": [
+                { "name": "Red", "kind": "unit" },
+                { "name": "Custom", "kind": "struct", "fields": [{ "name": "color", "type": "Color" }] }
+            ] },
+            { "name": "Visibility", "component": true, "kind": "struct" }
+        ],
+        "commands": [
+            { "name": "InsertSpriteBundle", "properties": [
+                { "name": "texture_path", "type": "String" }
+            ] },
+            { "name": "Custom" }
+        ]
+    }"#;
+
+    /// Diagnose a prefab with a registry that only has the manifest's types.
+    fn diagnose(manifest: &TypeManifest, input: &str) -> Vec<PrefabDiagnostic> {
+        let mut registry = PrefabRegistry::default();
+        registry.register_manifest(manifest);
+        registry.clear_sources();
+        registry.add_source(MemoryPrefabSource::from_iter([("knight.prefab", input)]));
+        registry.diagnose("knight.prefab")
+    }
+
+    #[test]
+    fn diagnose_with_manifest() {
+        let manifest = TypeManifest::from_json(MANIFEST).unwrap();
+        let valid = "Knight {
+            params { level: u8 = 1 },
+            Transform { translation: Vec3 { y: 1.0 } },
+            Unit { stats: Stats { level: $level } },
+            Team::Custom { color: Color::RED },
+            InsertSpriteBundle!(texture_path: \"knight.png\"),
+            Custom!(anything: 1),
+            children: [ { Visibility } ],
+        }";
+        assert!(diagnose(&manifest, valid).is_empty());
+
+        let invalid = "Knight {
+            params { level: Level = 1 },
+            Transfrom { translation: Vec3 { y: 1.0 } },
+            Unit { stats: Stats { levle: 2 } },
+            Team::Blue,
+            Team::Custom { colour: Color::RED },
+            Stats { level: 1 },
+            Missing!(),
+            InsertSpriteBundle!(texture: \"knight.png\"),
+            children: [ { Unit { stats: Other { x: 1 } } } ],
+            Unit { stats: Stats { level: 1.5 } },
+            Team::Custom(Color::RED),
+        }";
+        let diagnostics = diagnose(&manifest, invalid);
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.location().unwrap().line, d.error.kind()))
+            .collect();
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                (2, LoadPrefabError::UnknownParamType(_)),
+                (3, LoadPrefabError::UnregisteredPrefabComponent(_)),
+                (4, LoadPrefabError::UnknownField { .. }),
+                (5, LoadPrefabError::UnknownEnumVariant(_, _)),
+                (6, LoadPrefabError::UnknownField { .. }),
+                (7, LoadPrefabError::MissingReflectComponent(_)),
+                (8, LoadPrefabError::UnregisteredBuildCommand(_)),
+                (9, LoadPrefabError::UnknownField { .. }),
+                (10, LoadPrefabError::UnregisteredPrefabComponent(_)),
+                (11, LoadPrefabError::MismatchedValueType(_, _, _)),
+                (12, LoadPrefabError::InvalidEnumVariantFields(_, _)),
+            ]
+        ));
+        assert_eq!(
+            diagnostics[0].location().unwrap().path.as_deref(),
+            Some("knight.prefab")
+        );
+
+        let diagnostics = diagnose(&manifest, "Knight { Unit ");
+        assert!(matches!(
+            diagnostics[0].error.kind(),
+            LoadPrefabError::PestParseError(_)
+        ));
+    }
+
+    #[test]
+    fn inherit_with_manifest() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_manifest(&TypeManifest::from_json(MANIFEST).unwrap());
+        registry.clear_sources();
+        registry.add_source(MemoryPrefabSource::from_iter([
+            (
+                "unit.prefab",
+                "{ Unit { stats: Stats { level: 1 } }, Team::Red }",
+            ),
+            (
+                "knight.prefab",
+                "Knight : \"unit.prefab\" { Unit { stats: Stats { levle: 2 } } }",
+            ),
+        ]));
+
+        let diagnostics = registry.diagnose("knight.prefab");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(
+            diagnostics[0].error.kind(),
+            LoadPrefabError::UnknownField { .. }
+        ));
+
+        // Prefabs that use described types can be loaded, but not spawned.
+        assert_eq!(registry.load("unit.prefab").unwrap().steps.len(), 2);
+    }
+
+    #[test]
+    fn registry_manifest() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Transform>();
+        registry.register_type::<Vec3>();
+        registry.register_type::<Mood>();
+        registry.register_build_command::<InsertSpriteBundle>();
+
+        let manifest = registry.manifest();
+        assert_eq!(
+            TypeManifest::from_json(&manifest.to_json()).unwrap(),
+            manifest
+        );
+
+        let transform = manifest.get_type("Transform").unwrap();
+        assert!(transform.component);
+        let fields = match &transform.kind {
+            ManifestKind::Struct { fields } => fields,
+            _ => panic!("Transform should be a struct"),
+        };
+        assert_eq!(fields[0].name, "translation");
+        assert_eq!(fields[0].type_name, "Vec3");
+        assert_eq!(
+            fields[0].default.as_deref(),
+            Some("Vec3 { x: 0.0, y: 0.0, z: 0.0 }")
+        );
+        assert!(!manifest.get_type("Vec3").unwrap().component);
+
+        let mood = manifest.get_type("Mood").unwrap();
+        assert_eq!(mood.default.as_deref(), Some("Mood::Calm"));
+        let variants = match &mood.kind {
+            ManifestKind::Enum { variants } => variants,
+            _ => panic!("Mood should be an enum"),
+        };
+        assert_eq!(variants[0].kind, ManifestVariantKind::Unit);
+        assert!(matches!(
+            &variants[1].kind,
+            ManifestVariantKind::Struct { fields } if fields[0].name == "level"
+        ));
+        assert_eq!(
+            variants[2].kind,
+            ManifestVariantKind::Tuple {
+                fields: vec!["u8".to_string(), "String".to_string()]
+            }
+        );
+
+        let command = &manifest.commands[0];
+        assert_eq!(command.name, "InsertSpriteBundle");
+        assert_eq!(command.properties.len(), 2);
+        assert_eq!(command.properties[0].type_name, "Color");
+
+        let input = "{
+            Transform { translation: Vec3 { y: 1.0 } },
+            Mood::Angry { level: 3 },
+            InsertSpriteBundle!(texture_pth: \"a.png\"),
+        }";
+        let diagnostics = diagnose(&manifest, input);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(
+            diagnostics[0].error.kind(),
+            LoadPrefabError::UnknownField { suggestion: Some(s), .. } if s == "texture_path"
+        ));
+    }
+}
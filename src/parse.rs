@@ -1,22 +1,37 @@
 use bevy::{
     prelude::*,
-    reflect::{DynamicList, DynamicStruct, DynamicTuple, DynamicTupleStruct, Reflect},
+    reflect::{
+        DynamicArray, DynamicEnum, DynamicList, DynamicMap, DynamicStruct, DynamicTuple,
+        DynamicTupleStruct, DynamicVariant, Enum, Map, Reflect, Struct, Tuple, TupleStruct,
+        TypeInfo as ReflectTypeInfo, VariantInfo, VariantType,
+    },
+    utils::get_short_name,
+};
+use pest::{
+    error::{Error, ErrorVariant, InputLocation},
+    iterators::Pair,
+    Parser, Position, Span,
 };
-use pest::{error::Error, iterators::Pair, Parser};
 use pest_derive::*;
 use std::{ops::Range, sync::Arc};
+
+use bevy::utils::HashMap;
 use thiserror::Error;
 
 use crate::{
+    bevy_commands::map_entities,
+    diagnostic::{ParseContext, PrefabDiagnostic},
     dynamic_cast::*,
+    manifest::{ManifestKind, ManifestType, ManifestVariantKind},
     prefab::PrefabBuildStep,
     prefab::*,
     registry::{PrefabRegistry, ReflectType, TypeInfo},
+    value_type::{check_field_name, parse_float, parse_int, ExpectedType},
 };
 
 #[derive(Parser)]
 #[grammar = "lazy_prefabs.pest"]
-struct PrefabParser;
+pub(crate) struct PrefabParser;
 
 /// A name/value pair representing a field on a type
 #[derive(Debug)]
@@ -25,6 +40,26 @@ struct ReflectField {
     pub value: Box<dyn Reflect>,
 }
 
+/// The data inside a component, written as fields inside curly braces,
+/// values inside parentheses or square brackets, or map entries.
+enum ComponentField {
+    Named(ReflectField),
+    Unnamed(Box<dyn Reflect>),
+    Entry(Box<dyn Reflect>, Box<dyn Reflect>),
+}
+
+impl ComponentField {
+    /// Returns the value of a positional field. Nested components are
+    /// treated as positional.
+    fn into_value(self) -> Option<Box<dyn Reflect>> {
+        match self {
+            ComponentField::Named(field) => Some(field.value),
+            ComponentField::Unnamed(value) => Some(value),
+            ComponentField::Entry(_, _) => None,
+        }
+    }
+}
+
 impl From<PrefabComponent> for ReflectField {
     fn from(comp: PrefabComponent) -> Self {
         ReflectField {
@@ -34,79 +69,922 @@ impl From<PrefabComponent> for ReflectField {
     }
 }
 
+/// Errors that can occur when loading a [Prefab].
+///
+/// Errors from parsing a *.prefab* file are wrapped in [LoadPrefabError::Located], which
+/// includes where in the file the error occurred.
 #[derive(Error, Debug)]
 pub enum LoadPrefabError {
-    #[error("Pest error parsing prefab string.")]
-    PestParseError(#[from] Error<Rule>),
+    #[error("Error parsing prefab - {}.", pest_message(.0))]
+    PestParseError(Box<Error<Rule>>),
     #[error("Error parsing prefab - unknown field rule: {0}.")]
     UnhandledPrefabFieldRule(String),
     #[error("Error parsing prefab - unknown component field rule: {0}.")]
     UnhandledPrefabComponentFieldRule(String),
     #[error("Error parsing component - {0} was not registered with the PrefabRegistry.")]
     UnregisteredPrefabComponent(String),
+    #[error("Error parsing component - {0} doesn't have the '#[reflect(Component)]' attribute.")]
+    MissingReflectComponent(String),
+    #[error("Error parsing command - {0} was not registered with the PrefabRegistry.")]
+    UnregisteredBuildCommand(String),
+    #[error("Error parsing '{0}' - the field was given more than once.")]
+    DuplicateField(String),
+    #[error("Error parsing component - the data given for {0} doesn't match it's type: {1}.")]
+    InvalidComponentData(String, String),
+    #[error("Error parsing enum - '{1}' is not a variant of {0}.")]
+    UnknownEnumVariant(String, String),
+    #[error("Error parsing enum - the fields given for {0}::{1} don't match the variant.")]
+    InvalidEnumVariantFields(String, String),
+    #[error("Error parsing component - the enum {0} must specify a variant, ie: '{0}::Variant'.")]
+    MissingEnumVariant(String),
+    #[error("Error parsing component - {0} is not an enum.")]
+    NotAnEnum(String),
+    #[error(
+        "Error parsing {type_name} - unknown field '{field}'.{} Valid fields are: {}.",
+        did_you_mean(.suggestion),
+        .valid.join(", ")
+    )]
+    UnknownField {
+        type_name: String,
+        field: String,
+        /// The closest valid field name, if any are similar.
+        suggestion: Option<String>,
+        valid: Vec<String>,
+    },
+    #[error("Error parsing '{0}' - expected a value of type {1}, found '{2}'.")]
+    MismatchedValueType(String, String, String),
     #[error("Error parsing value type '{0}' from '{1}'.")]
     ValueParseError(String, String),
+    #[error("Error parsing prefab - more than one prefab in the file is named '{0}'.")]
+    DuplicatePrefabName(String),
+    #[error("Error loading prefab - '{0}' has no prefab named '{1}'.")]
+    UnknownPrefabName(String, String),
+    #[error("Error loading prefab - '{0}' doesn't contain any prefabs.")]
+    MissingPrefab(String),
+    #[error("Error parsing prefab - more than one component group in the file is named '{0}'.")]
+    DuplicateGroupName(String),
+    #[error(
+        "Error parsing '..{0}' - no component group is named '{0}'. Groups must be declared in the file or a file it imports."
+    )]
+    UnknownGroup(String),
+    #[error(
+        "Error parsing prefab - component groups splice each other in a cycle: {}.",
+        .0.join(" -> ")
+    )]
+    GroupCycle(Vec<String>),
+    #[error("Error parsing prefab - more than one entity is named '#{0}'.")]
+    DuplicateEntityName(String),
+    #[error("Error parsing '@{0}' - no entity in the prefab is named '#{0}'.")]
+    UnknownEntityName(String),
+    #[error("Error parsing '@{0}' - entities can only be referred to in components.")]
+    MisplacedEntityReference(String),
+    #[error("Error parsing '${0}' - the prefab has no parameter named '{0}'.")]
+    UnknownParam(String),
+    #[error("Error parsing parameter - {0} is not a registered or built in type.")]
+    UnknownParamType(String),
+    #[error(
+        "Error parsing '${0}' - parameters can't be used in parameter defaults or in the fields of Vec2, Vec3, Quat and Color values."
+    )]
+    MisplacedParam(String),
     #[error("Error parsing prefab - unknown value rule: {0}.")]
     UnhandledValueRule(String),
-    #[error("Error reading prefab file.")]
-    FileReadError(#[from] bevy::asset::AssetIoError),
+    #[error(
+        "Error loading prefab - prefabs load or inherit from each other in a cycle: {}.",
+        .0.join(" -> ")
+    )]
+    Cycle(Vec<String>),
+    #[error("Error reading prefab file '{0}'.")]
+    FileReadError(String, #[source] bevy::asset::AssetIoError),
+    #[error("{error}\n{location}")]
+    Located {
+        error: Box<LoadPrefabError>,
+        location: ErrorLocation,
+    },
+}
+
+impl LoadPrefabError {
+    /// The error without it's location.
+    pub fn kind(&self) -> &LoadPrefabError {
+        match self {
+            LoadPrefabError::Located { error, .. } => error.kind(),
+            _ => self,
+        }
+    }
+
+    /// Where in the *.prefab* file the error occurred, if known.
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match self {
+            LoadPrefabError::Located { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// Attach a location to the error. Errors that already have a location keep
+    /// their original, more specific, location.
+    pub(crate) fn at(self, span: &Span) -> Self {
+        match self {
+            LoadPrefabError::Located { .. } => self,
+            _ => LoadPrefabError::Located {
+                error: Box::new(self),
+                location: ErrorLocation::new(&span.start_pos(), span.as_str()),
+            },
+        }
+    }
+
+    /// Set the path of the prefab file the error occurred in. Errors that occurred in
+    /// a base prefab keep the base prefab's path.
+    pub(crate) fn with_path(self, path: &str) -> Self {
+        match self {
+            LoadPrefabError::Located {
+                error,
+                mut location,
+            } => {
+                location.path.get_or_insert_with(|| path.to_string());
+                LoadPrefabError::Located { error, location }
+            }
+            _ => self,
+        }
+    }
+
+    fn from_pest(error: Error<Rule>, input: &str) -> Self {
+        let pos = match error.location {
+            InputLocation::Pos(pos) => pos,
+            InputLocation::Span((start, _)) => start,
+        };
+        let location = Position::new(input, pos)
+            .map(|pos| ErrorLocation::new(&pos, ""))
+            .unwrap_or_default();
+        LoadPrefabError::Located {
+            error: Box::new(LoadPrefabError::PestParseError(Box::new(error))),
+            location,
+        }
+    }
 }
 
-pub(crate) fn parse_prefab_string(
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(" Did you mean '{}'?", suggestion),
+        None => String::new(),
+    }
+}
+
+fn pest_message(error: &Error<Rule>) -> String {
+    fn rules(rules: &[Rule]) -> String {
+        rules
+            .iter()
+            .map(|r| format!("{:?}", r))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    match &error.variant {
+        ErrorVariant::ParsingError {
+            positives,
+            negatives,
+        } => match (positives.is_empty(), negatives.is_empty()) {
+            (false, false) => format!(
+                "unexpected {}; expected {}",
+                rules(negatives),
+                rules(positives)
+            ),
+            (false, true) => format!("expected {}", rules(positives)),
+            (true, false) => format!("unexpected {}", rules(negatives)),
+            (true, true) => "unknown parsing error".to_string(),
+        },
+        ErrorVariant::CustomError { message } => message.clone(),
+    }
+}
+
+/// The location of an error in a *.prefab* file.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorLocation {
+    /// The path of the prefab file, if it was loaded from a file.
+    pub path: Option<String>,
+    /// The line the error occurred on, starting from 1.
+    pub line: usize,
+    /// The column the error occurred at, starting from 1.
+    pub column: usize,
+    /// The line of source the error occurred on, with a caret underneath the error.
+    pub snippet: String,
+}
+
+impl ErrorLocation {
+    fn new(pos: &Position, text: &str) -> Self {
+        let (line, column) = pos.line_col();
+        let source = pos.line_of().trim_end_matches(&['\r', '\n'][..]);
+
+        // Underline the text on the error line, or just the start of the error if the
+        // text spans multiple lines.
+        let len = text.lines().next().map(|l| l.chars().count()).unwrap_or(0);
+        let underline = match len {
+            0 | 1 => "^".to_string(),
+            len => format!("^{}^", "-".repeat(len - 2)),
+        };
+
+        ErrorLocation {
+            path: None,
+            line,
+            column,
+            snippet: format!("{}\n{}{}", source, " ".repeat(column - 1), underline),
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        let path = self.path.as_deref().unwrap_or("<prefab>");
+        writeln!(f, "{}--> {}:{}:{}", gutter, path, self.line, self.column)?;
+        writeln!(f, "{} |", gutter)?;
+        let mut lines = self.snippet.lines();
+        if let Some(source) = lines.next() {
+            writeln!(f, "{} | {}", self.line, source)?;
+        }
+        for line in lines {
+            writeln!(f, "{} | {}", gutter, line)?;
+        }
+        write!(f, "{} |", gutter)
+    }
+}
+
+/// Split a prefab's name into the file name and the name of the prefab in the file, ie:
+/// `enemies.prefab#Orc`.
+pub(crate) fn split_prefab_name(name: &str) -> (&str, Option<&str>) {
+    match name.split_once('#') {
+        Some((file, prefab)) => (file, Some(prefab)),
+        None => (name, None),
+    }
+}
+
+/// The imports, component groups and prefabs in a prefab file.
+struct PrefabFile<'i> {
+    imports: Vec<String>,
+    groups: Vec<Pair<'i, Rule>>,
+    prefabs: Vec<Pair<'i, Rule>>,
+}
+
+/// Parse the items in a prefab file, checking that the names of it's prefabs and groups
+/// are unique.
+fn parse_prefab_items(input: &str) -> Result<PrefabFile<'_>, LoadPrefabError> {
+    let pairs = PrefabParser::parse(Rule::prefab_file, input)
+        .map_err(|e| LoadPrefabError::from_pest(e, input))?
+        .next()
+        .unwrap()
+        .into_inner();
+
+    let mut file = PrefabFile {
+        imports: Vec::new(),
+        groups: Vec::new(),
+        prefabs: Vec::new(),
+    };
+    let mut prefab_names = Vec::new();
+    let mut group_names = Vec::new();
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::import => file
+                .imports
+                .push(parse_string(pair.into_inner().next().unwrap())),
+            Rule::group => {
+                let name = group_name(&pair);
+                if group_names.contains(&name) {
+                    let error = LoadPrefabError::DuplicateGroupName(name.to_string());
+                    return Err(error.at(&pair.as_span()));
+                }
+                group_names.push(name);
+                file.groups.push(pair);
+            }
+            Rule::prefab => {
+                if let Some(name) = prefab_pair_name(&pair) {
+                    if prefab_names.contains(&name) {
+                        let error = LoadPrefabError::DuplicatePrefabName(name.to_string());
+                        return Err(error.at(&pair.as_span()));
+                    }
+                    prefab_names.push(name);
+                }
+                file.prefabs.push(pair);
+            }
+            _ => {}
+        }
+    }
+    Ok(file)
+}
+
+fn group_name<'i>(pair: &Pair<'i, Rule>) -> &'i str {
+    pair.clone().into_inner().next().unwrap().as_str()
+}
+
+fn prefab_pair_name<'i>(pair: &Pair<'i, Rule>) -> Option<&'i str> {
+    pair.clone()
+        .into_inner()
+        .next()
+        .filter(|p| p.as_rule() == Rule::type_name)
+        .map(|p| p.as_str())
+}
+
+/// Check that a prefab file is syntactically valid, without checking the types it uses.
+pub(crate) fn check_syntax(input: &str) -> Result<(), LoadPrefabError> {
+    PrefabParser::parse(Rule::prefab_file, input)
+        .map(|_| ())
+        .map_err(|e| LoadPrefabError::from_pest(e, input))
+}
+
+/// Parse a prefab file, setting the prefab's path and the path of any errors.
+///
+/// If the name refers to a prefab in the file, ie: `enemies.prefab#Orc`, that prefab is
+/// parsed, otherwise the first prefab in the file is.
+pub(crate) fn parse_prefab_file(
+    name: &str,
+    input: &str,
+    registry: &PrefabRegistry,
+) -> Result<Prefab, LoadPrefabError> {
+    parse_named_prefab(name, input, &ParseContext::new(registry).named(name))
+}
+
+fn parse_named_prefab(
+    name: &str,
     input: &str,
-    registry: &mut PrefabRegistry,
+    ctx: &ParseContext,
 ) -> Result<Prefab, LoadPrefabError> {
-    let mut parsed = PrefabParser::parse(Rule::prefab, input)?;
+    let (file, label) = split_prefab_name(name);
+    let items = parse_prefab_items(input).map_err(|e| e.with_path(file))?;
+    let pair = match label {
+        Some(label) => items
+            .prefabs
+            .iter()
+            .find(|pair| prefab_pair_name(pair) == Some(label))
+            .ok_or_else(|| {
+                LoadPrefabError::UnknownPrefabName(file.to_string(), label.to_string())
+            })?,
+        None => items
+            .prefabs
+            .first()
+            .ok_or_else(|| LoadPrefabError::MissingPrefab(file.to_string()))?,
+    };
+
+    let imported = read_imports(file, &items.imports, ctx).map_err(|e| e.with_path(file))?;
+    let groups = file_groups(file, &items.groups, &imported)?;
+    parse_file_prefab(name, pair, ctx, &groups, &imported)
+}
+
+/// Parse every prefab in a file, only reading it's imports once. Returns the first prefab in
+/// the file, followed by each named prefab and it's name, ie: `Orc` for `enemies.prefab#Orc`.
+pub(crate) fn parse_prefab_file_all(
+    file: &str,
+    input: &str,
+    registry: &PrefabRegistry,
+) -> Result<(Prefab, Vec<(String, Prefab)>), LoadPrefabError> {
+    let items = parse_prefab_items(input).map_err(|e| e.with_path(file))?;
+    let ctx = ParseContext::new(registry).named(file);
+    let imported = read_imports(file, &items.imports, &ctx).map_err(|e| e.with_path(file))?;
+    let groups = file_groups(file, &items.groups, &imported)?;
+
+    let mut first = None;
+    let mut named = Vec::new();
+    for pair in items.prefabs.iter() {
+        let label = prefab_pair_name(pair);
+        let name = match label {
+            Some(label) => format!("{}#{}", file, label),
+            None => file.to_string(),
+        };
+        let ctx = ParseContext::new(registry).named(&name);
+        let prefab = parse_file_prefab(&name, pair, &ctx, &groups, &imported)?;
+        if first.is_none() {
+            let mut prefab = prefab.clone();
+            prefab.path = Some(file.to_string());
+            first = Some(prefab);
+        }
+        if let Some(label) = label {
+            named.push((label.to_string(), prefab));
+        }
+    }
+    let first = first.ok_or_else(|| LoadPrefabError::MissingPrefab(file.to_string()))?;
+    Ok((first, named))
+}
 
-    parse_prefab(parsed.next().unwrap(), registry)
+/// Parse one of the prefabs in a file, given the file's component groups and imports.
+fn parse_file_prefab(
+    name: &str,
+    pair: &Pair<Rule>,
+    ctx: &ParseContext,
+    groups: &Groups,
+    imported: &[(String, String)],
+) -> Result<Prefab, LoadPrefabError> {
+    let (file, _) = split_prefab_name(name);
+    let mut prefab = parse_prefab(pair.clone(), ctx, groups)
+        .and_then(|prefab| {
+            ctx.check_entity_names(&prefab.entity_names())?;
+            Ok(prefab)
+        })
+        .map_err(|e| e.with_path(file))?;
+    prefab.path = Some(name.to_string());
+    prefab.imports = imported.iter().map(|(name, _)| name.clone()).collect();
+    Ok(prefab)
 }
 
-fn parse_prefab(pair: Pair<Rule>, registry: &PrefabRegistry) -> Result<Prefab, LoadPrefabError> {
+/// A group of components and build commands, declared with `group Name { ... }` and spliced
+/// into prefabs with `..Name`.
+#[derive(Clone)]
+struct ComponentGroup<'i> {
+    /// The file the group is declared in.
+    file: &'i str,
+    pair: Pair<'i, Rule>,
+}
+
+/// The component groups a prefab can splice, by name.
+type Groups<'i> = HashMap<&'i str, ComponentGroup<'i>>;
+
+/// Read the files imported by a prefab file and the files they import, returning their
+/// names and text. Each file is only read once.
+fn read_imports(
+    file: &str,
+    imports: &[String],
+    ctx: &ParseContext,
+) -> Result<Vec<(String, String)>, LoadPrefabError> {
+    let mut files: Vec<(String, String)> = Vec::new();
+    let mut remaining: Vec<String> = imports.iter().rev().cloned().collect();
+    while let Some(name) = remaining.pop() {
+        if name == file || files.iter().any(|(n, _)| *n == name) {
+            continue;
+        }
+        let (text, _) = ctx.registry.read_prefab_string(&name)?;
+        let items = parse_prefab_items(&text).map_err(|e| e.with_path(&name))?;
+        remaining.extend(items.imports.into_iter().rev());
+        files.push((name, text));
+    }
+    Ok(files)
+}
+
+/// The component groups a prefab file can splice. Groups declared in the file replace
+/// imported groups with the same name.
+fn file_groups<'i>(
+    file: &'i str,
+    groups: &[Pair<'i, Rule>],
+    imported: &'i [(String, String)],
+) -> Result<Groups<'i>, LoadPrefabError> {
+    let mut all = Groups::default();
+    for (name, text) in imported {
+        let items = parse_prefab_items(text).map_err(|e| e.with_path(name))?;
+        for pair in items.groups {
+            all.insert(group_name(&pair), ComponentGroup { file: name, pair });
+        }
+    }
+    for pair in groups.iter().cloned() {
+        all.insert(group_name(&pair), ComponentGroup { file, pair });
+    }
+    Ok(all)
+}
+
+/// Parse a prefab, collecting every problem in it instead of stopping at the first error.
+pub(crate) fn diagnose_prefab_string(
+    file: &str,
+    input: &str,
+    registry: &PrefabRegistry,
+) -> Vec<PrefabDiagnostic> {
+    let items = match parse_prefab_items(input) {
+        Ok(items) => items,
+        Err(e) => return vec![PrefabDiagnostic::error(e)],
+    };
+
+    let imported = read_imports(file, &items.imports, &ParseContext::new(registry));
+    let imported = match imported {
+        Ok(imported) => imported,
+        Err(e) => return vec![PrefabDiagnostic::error(e)],
+    };
+    let groups = match file_groups(file, &items.groups, &imported) {
+        Ok(groups) => groups,
+        Err(e) => return vec![PrefabDiagnostic::error(e)],
+    };
+
+    let mut diagnostics = Vec::new();
+    for pair in items.prefabs {
+        let ctx = ParseContext::diagnostic(registry);
+        let result = parse_prefab(pair, &ctx, &groups)
+            .and_then(|prefab| ctx.check_entity_names(&prefab.entity_names()));
+        // Errors that can't be recovered from still end up in the diagnostics.
+        let _ = ctx.recover(result);
+        diagnostics.extend(ctx.into_diagnostics());
+    }
+    diagnostics
+}
+
+/// The build steps of a prefab being parsed.
+#[derive(Default)]
+struct PrefabSteps {
+    steps: Vec<PrefabBuildStep>,
+    /// The steps added by the base prefab or spliced from component groups. Components given
+    /// again are merged into these.
+    shared: Vec<usize>,
+    /// The component groups being spliced, outermost first.
+    splicing: Vec<String>,
+}
+
+impl PrefabSteps {
+    fn inherit(&mut self, steps: &[PrefabBuildStep]) {
+        self.steps = steps.to_vec();
+        self.shared = (0..steps.len()).collect();
+    }
+
+    fn add_component(&mut self, comp: PrefabComponent, shared: bool) {
+        let existing = self.shared.iter().find_map(|&i| match &self.steps[i] {
+            PrefabBuildStep::AddComponent(c) if c.type_name == comp.type_name => Some((i, c)),
+            _ => None,
+        });
+        match existing {
+            Some((i, existing)) => {
+                let merged = merge_component(existing, comp);
+                self.steps[i] = PrefabBuildStep::AddComponent(Arc::new(merged));
+            }
+            None => {
+                if shared {
+                    self.shared.push(self.steps.len());
+                }
+                self.steps
+                    .push(PrefabBuildStep::AddComponent(Arc::new(comp)));
+            }
+        }
+    }
+}
+
+fn parse_prefab(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+    groups: &Groups,
+) -> Result<Prefab, LoadPrefabError> {
+    // Only the root prefab can declare parameters, children share them.
+    let is_root = pair.as_rule() == Rule::prefab;
     let mut name = None;
-    let mut steps = Vec::new();
+    let mut base = None;
+    let mut entity_name = None;
+    let mut steps = PrefabSteps::default();
 
     for field in pair.into_inner() {
         match field.as_rule() {
             Rule::type_name => {
                 name = Some(field.as_str().to_string());
             }
-            Rule::component => {
-                let comp = parse_component(field, registry)?;
-                steps.push(PrefabBuildStep::AddComponent(Arc::new(comp)));
+            Rule::entity_name => {
+                let text = field.as_str()[1..].to_string();
+                let error = LoadPrefabError::DuplicateEntityName(text.clone());
+                ctx.name_entity(&text, error.at(&field.as_span()));
+                entity_name = Some(text);
+            }
+            Rule::prefab_base => {
+                let base_name = parse_string(field.clone().into_inner().next().unwrap());
+                let span = field.as_span();
+                let base_prefab = load_base(&base_name, ctx).map_err(|e| e.at(&span));
+                if let Some(base_prefab) = ctx.recover(base_prefab)? {
+                    for param in base_prefab.params.iter() {
+                        ctx.declare_param(param.clone());
+                    }
+                    steps.inherit(&base_prefab.steps);
+                    base = Some(base_name);
+                }
             }
-            Rule::command => {
-                let command = parse_command(field, registry)?;
-                steps.push(PrefabBuildStep::RunCommand(Arc::new(command)));
+            Rule::params => {
+                for param in field.into_inner() {
+                    if let Some(param) = ctx.recover(parse_param(param, ctx))? {
+                        ctx.declare_param(Arc::new(param));
+                    }
+                }
             }
-            _ => {
-                let str = format!("{:#?}", field.as_rule());
-                return Err(LoadPrefabError::UnhandledPrefabFieldRule(str));
+            _ => parse_prefab_field(field, ctx, groups, &mut steps, false)?,
+        }
+    }
+
+    Ok(Prefab {
+        name,
+        path: None,
+        base,
+        entity_name,
+        params: if is_root { ctx.params() } else { Vec::new() },
+        imports: Vec::new(),
+        steps: steps.steps,
+    })
+}
+
+/// Parse a component, build command, list of children or group splice. `shared` is true
+/// for fields spliced from a component group.
+fn parse_prefab_field(
+    field: Pair<Rule>,
+    ctx: &ParseContext,
+    groups: &Groups,
+    steps: &mut PrefabSteps,
+    shared: bool,
+) -> Result<(), LoadPrefabError> {
+    match field.as_rule() {
+        Rule::component | Rule::enum_value => {
+            // Components given for the base prefab's or a group's components are merged
+            // into them.
+            if let Some(comp) = ctx.recover(parse_prefab_component(field, ctx))? {
+                steps.add_component(comp, shared);
+            }
+        }
+        Rule::command => {
+            if let Some(command) = ctx.recover(parse_command(field, ctx))? {
+                steps
+                    .steps
+                    .push(PrefabBuildStep::RunCommand(Arc::new(command)));
+            }
+        }
+        Rule::children => {
+            for child in field.into_inner() {
+                if let Some(child) = ctx.recover(parse_prefab(child, ctx, groups))? {
+                    steps
+                        .steps
+                        .push(PrefabBuildStep::SpawnChild(Arc::new(child)));
+                }
+            }
+        }
+        Rule::splice => {
+            let span = field.as_span();
+            let spliced = splice_group(&field.as_str()[2..], ctx, groups, steps);
+            ctx.recover(spliced.map_err(|e| e.at(&span)))?;
+        }
+        _ => {
+            let str = format!("{:#?}", field.as_rule());
+            return Err(LoadPrefabError::UnhandledPrefabFieldRule(str).at(&field.as_span()));
+        }
+    }
+    Ok(())
+}
+
+/// Add the fields of a component group to a prefab, as if they were written in place of
+/// the splice.
+fn splice_group(
+    name: &str,
+    ctx: &ParseContext,
+    groups: &Groups,
+    steps: &mut PrefabSteps,
+) -> Result<(), LoadPrefabError> {
+    let group = groups
+        .get(name)
+        .ok_or_else(|| LoadPrefabError::UnknownGroup(name.to_string()))?;
+    if let Some(start) = steps.splicing.iter().position(|n| n == name) {
+        let mut cycle = steps.splicing[start..].to_vec();
+        cycle.push(name.to_string());
+        return Err(LoadPrefabError::GroupCycle(cycle));
+    }
+
+    steps.splicing.push(name.to_string());
+    for field in group.pair.clone().into_inner().skip(1) {
+        parse_prefab_field(field, ctx, groups, steps, true).map_err(|e| e.with_path(group.file))?;
+    }
+    steps.splicing.pop();
+    Ok(())
+}
+
+/// Parse a parameter declaration, ie: `hp: i32 = 10`.
+fn parse_param(pair: Pair<Rule>, ctx: &ParseContext) -> Result<PrefabParam, LoadPrefabError> {
+    let span = pair.as_span();
+    read_param(pair, ctx).map_err(|e| e.at(&span))
+}
+
+fn read_param(pair: Pair<Rule>, ctx: &ParseContext) -> Result<PrefabParam, LoadPrefabError> {
+    let mut pairs = pair.into_inner();
+    let name = pairs.next().unwrap().as_str().to_string();
+    let type_pair = pairs.next().unwrap();
+    let type_name = type_pair.as_str();
+    let path = format!("${}", name);
+
+    let expected =
+        ExpectedType::from_name(ctx.registry, path.clone(), type_name).ok_or_else(|| {
+            LoadPrefabError::UnknownParamType(type_name.to_string()).at(&type_pair.as_span())
+        })?;
+    ctx.track_params();
+    let default = parse_value(pairs.next().unwrap(), ctx, &expected)?;
+    if let Some(used) = ctx.used_param() {
+        return Err(LoadPrefabError::MisplacedParam(used));
+    }
+
+    // Parameters redeclared from a base prefab can change their default, but not their type.
+    if let Some(existing) = ctx.param(&name) {
+        if existing.default.type_name() != default.type_name() {
+            return Err(LoadPrefabError::MismatchedValueType(
+                path,
+                get_short_name(existing.default.type_name()),
+                type_name.to_string(),
+            ));
+        }
+    }
+
+    Ok(PrefabParam { name, default })
+}
+
+/// Load a prefab that's inherited from. Prefabs already cached in the registry are reused,
+/// otherwise the prefab is read from the registry's sources.
+fn load_base(name: &str, ctx: &ParseContext) -> Result<Arc<Prefab>, LoadPrefabError> {
+    let ctx = ctx.inherit(name)?;
+    if let Some(prefab) = ctx.registry.cached_prefab(name) {
+        return Ok(prefab.clone());
+    }
+
+    let (input, _) = ctx.registry.read_prefab_string(name)?;
+    parse_named_prefab(name, &input, &ctx).map(Arc::new)
+}
+
+/// Merge a component over a base prefab's component of the same type.
+fn merge_component(base: &PrefabComponent, comp: PrefabComponent) -> PrefabComponent {
+    let mut reflect = comp.reflect;
+    let mut entity_refs = base.entity_refs.clone();
+    if !comp.entity_refs.is_empty() {
+        // Placeholders from the component are moved after the base component's.
+        let offset = entity_refs.len();
+        map_entities(
+            &mut *reflect,
+            &mut |entity| match PrefabComponent::placeholder_index(entity) {
+                Some(index) => PrefabComponent::entity_placeholder(index + offset),
+                None => entity,
+            },
+        );
+        entity_refs.extend(comp.entity_refs);
+    }
+
+    PrefabComponent {
+        type_name: comp.type_name,
+        reflect: merge_value(&*base.reflect, &*reflect),
+        entity_refs,
+        uses_params: base.uses_params || comp.uses_params,
+    }
+}
+
+/// Merge a value over a base value. Structs, tuple structs, maps and enums of the same
+/// variant written in the prefab are merged one field or entry at a time, other values
+/// replace the base value entirely.
+fn merge_value(base: &dyn Reflect, value: &dyn Reflect) -> Box<dyn Reflect> {
+    if let (Some(base), Some(value)) = (
+        base.downcast_ref::<DynamicStruct>(),
+        value.downcast_ref::<DynamicStruct>(),
+    ) {
+        let mut merged = base.clone_dynamic();
+        for (i, field) in value.iter_fields().enumerate() {
+            let name = value.name_at(i).unwrap();
+            merged.insert_boxed(name, merge_field(base.field(name), field));
+        }
+        return Box::new(merged);
+    }
+
+    if let (Some(base), Some(value)) = (
+        base.downcast_ref::<DynamicTupleStruct>(),
+        value.downcast_ref::<DynamicTupleStruct>(),
+    ) {
+        let mut merged = DynamicTupleStruct::default();
+        merged.set_name(value.name().to_string());
+        for i in 0..base.field_len().max(value.field_len()) {
+            let field = match value.field(i) {
+                Some(field) => merge_field(base.field(i), field),
+                None => base.field(i).unwrap().clone_value(),
+            };
+            merged.insert_boxed(field);
+        }
+        return Box::new(merged);
+    }
+
+    if let (Some(base), Some(value)) = (
+        base.downcast_ref::<DynamicMap>(),
+        value.downcast_ref::<DynamicMap>(),
+    ) {
+        let mut merged = base.clone_dynamic();
+        for (key, field) in value.iter() {
+            merged.insert_boxed(key.clone_value(), merge_field(base.get(key), field));
+        }
+        return Box::new(merged);
+    }
+
+    if let (Some(base), Some(value)) = (
+        base.downcast_ref::<DynamicEnum>(),
+        value.downcast_ref::<DynamicEnum>(),
+    ) {
+        if base.variant_name() == value.variant_name() {
+            return Box::new(merge_variant(base, value));
+        }
+    }
+
+    value.clone_value()
+}
+
+fn merge_field(base: Option<&dyn Reflect>, value: &dyn Reflect) -> Box<dyn Reflect> {
+    match base {
+        Some(base) => merge_value(base, value),
+        None => value.clone_value(),
+    }
+}
+
+/// Merge the fields of two values of the same enum variant.
+fn merge_variant(base: &DynamicEnum, value: &DynamicEnum) -> DynamicEnum {
+    let variant = match value.variant_type() {
+        VariantType::Unit => DynamicVariant::Unit,
+        VariantType::Tuple => {
+            let mut tuple = DynamicTuple::default();
+            for (i, field) in value.iter_fields().enumerate() {
+                tuple.insert_boxed(merge_field(base.field_at(i), field.value()));
+            }
+            DynamicVariant::Tuple(tuple)
+        }
+        VariantType::Struct => {
+            let mut data_struct = DynamicStruct::default();
+            for field in value.iter_fields() {
+                let name = field.name().unwrap();
+                data_struct.insert_boxed(name, merge_field(base.field(name), field.value()));
             }
+            DynamicVariant::Struct(data_struct)
         }
+    };
+    DynamicEnum::new(value.type_name(), value.variant_name(), variant)
+}
+
+/// Parse a component that gets added to the prefab entity. The component's type must have
+/// the `#[reflect(Component)]` attribute, or be described as a component.
+fn parse_prefab_component(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+) -> Result<PrefabComponent, LoadPrefabError> {
+    let span = pair.as_span();
+    ctx.begin_component();
+    ctx.track_params();
+    let comp = match pair.as_rule() {
+        Rule::enum_value => parse_enum_component(pair, ctx),
+        _ => parse_component(pair, ctx, ""),
+    };
+    let entity_refs = ctx.end_component();
+    let comp = PrefabComponent {
+        entity_refs,
+        uses_params: ctx.used_param().is_some(),
+        ..comp?
+    };
+
+    if !ctx.registry.is_component(&comp.type_name) {
+        return Err(LoadPrefabError::MissingReflectComponent(comp.type_name).at(&span));
     }
 
-    Ok(Prefab { name, steps })
+    Ok(comp)
 }
 
+/// Parse a component and it's fields. Field values are parsed as the types of the
+/// matching fields on the registered component type.
+///
+/// `path` is the path to the component for error messages, or empty for a root component.
 fn parse_component(
     pair: Pair<Rule>,
-    registry: &PrefabRegistry,
+    ctx: &ParseContext,
+    path: &str,
+) -> Result<PrefabComponent, LoadPrefabError> {
+    let span = pair.as_span();
+    read_component(pair, ctx, path).map_err(|e| e.at(&span))
+}
+
+fn read_component(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+    path: &str,
 ) -> Result<PrefabComponent, LoadPrefabError> {
     let mut fields = Vec::new();
 
-    let mut pairs = pair.into_inner();
+    let mut pairs = pair.clone().into_inner();
     let type_name = pairs.next().unwrap().as_str();
 
+    let t = ctx.registry.get_type_data(type_name);
+    let described = ctx.registry.get_described_type(type_name);
+    let unregistered = || LoadPrefabError::UnregisteredPrefabComponent(type_name.to_string());
+    // In diagnostic mode the fields of an unregistered component are still parsed,
+    // so errors inside them are reported too.
+    if t.is_none() && described.is_none() && !ctx.is_diagnostic() {
+        return Err(unregistered());
+    }
+    let path = if path.is_empty() { type_name } else { path };
+    let expected = match (t, described) {
+        (Some(t), _) => ExpectedType::registered(path, t),
+        (None, Some(described)) => ExpectedType::described(path, described),
+        (None, None) => ExpectedType::unknown(path),
+    };
+
+    ctx.recover(check_duplicate_fields(&pair, path))?;
+
     // Prefab fields
     for field in pairs {
         match field.as_rule() {
             Rule::component => {
-                let nested_component = parse_component(field, registry).unwrap();
-                fields.push(ReflectField::from(nested_component));
+                let path = expected.element(ctx.registry, fields.len()).path;
+                let nested_component = parse_component(field, ctx, &path);
+                if let Some(nested_component) = ctx.recover(nested_component)? {
+                    fields.push(ComponentField::Named(nested_component.into()));
+                }
             }
             Rule::field => {
-                let field = parse_field(field, registry)?;
-                fields.push(field);
+                if let Some(field) = ctx.recover(parse_field(field, ctx, &expected))? {
+                    fields.push(ComponentField::Named(field));
+                }
+            }
+            Rule::map_entry => {
+                if let Some(entry) = ctx.recover(parse_map_entry(field, ctx, &expected))? {
+                    fields.push(entry);
+                }
+            }
+            Rule::component_tuple | Rule::array => {
+                for value in field.into_inner() {
+                    let value_type = expected.element(ctx.registry, fields.len());
+                    let value = parse_value(value, ctx, &value_type)?;
+                    fields.push(ComponentField::Unnamed(value));
+                }
             }
             _ => {
                 let str = format!("{:#?}", field.as_rule());
@@ -114,57 +992,433 @@ fn parse_component(
             }
         }
     }
-    let t = registry
-        .get_type_data(type_name)
-        .ok_or_else(|| LoadPrefabError::UnregisteredPrefabComponent(type_name.to_string()))?;
 
-    let comp = build_component(t, fields);
+    let comp = match (t, described) {
+        (Some(t), _) => build_component(t, fields)?,
+        (None, Some(described)) => build_described(described, fields)?,
+        (None, None) => return Err(unregistered()),
+    };
 
     Ok(PrefabComponent {
         type_name: type_name.to_string(),
         reflect: comp,
+        entity_refs: Vec::new(),
+        uses_params: false,
     })
 }
 
-fn build_component(type_info: &TypeInfo, fields: Vec<ReflectField>) -> Box<dyn Reflect> {
-    match type_info.reflect_type {
+fn parse_map_entry(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+    map: &ExpectedType,
+) -> Result<ComponentField, LoadPrefabError> {
+    let mut entry = pair.into_inner();
+    let key = entry.next().unwrap();
+    let key_text = key.as_str();
+    let key = parse_value(key, ctx, &map.map_key(ctx.registry))?;
+    let value = entry.next().unwrap();
+    let value = parse_value(value, ctx, &map.map_value(ctx.registry, key_text))?;
+    Ok(ComponentField::Entry(key, value))
+}
+
+/// Parse a component written as an enum variant, ie: `Faction::Enemy(3)`.
+fn parse_enum_component(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+) -> Result<PrefabComponent, LoadPrefabError> {
+    let span = pair.as_span();
+    read_enum_component(pair, ctx).map_err(|e| e.at(&span))
+}
+
+fn read_enum_component(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+) -> Result<PrefabComponent, LoadPrefabError> {
+    let type_name = pair.clone().into_inner().next().unwrap().as_str();
+
+    let expected = match ctx.registry.get_type_data(type_name) {
+        Some(t) if t.reflect_type == ReflectType::Enum => ExpectedType::registered(type_name, t),
+        Some(_) => return Err(LoadPrefabError::NotAnEnum(type_name.to_string())),
+        None => match ctx.registry.get_described_type(type_name) {
+            Some(t) if matches!(t.kind, ManifestKind::Enum { .. }) => {
+                ExpectedType::described(type_name, t)
+            }
+            Some(_) => return Err(LoadPrefabError::NotAnEnum(type_name.to_string())),
+            None => {
+                let error = LoadPrefabError::UnregisteredPrefabComponent(type_name.to_string());
+                return Err(error);
+            }
+        },
+    };
+
+    let comp = parse_enum(pair, ctx, &expected)?;
+
+    Ok(PrefabComponent {
+        type_name: type_name.to_string(),
+        reflect: Box::new(comp),
+        entity_refs: Vec::new(),
+        uses_params: false,
+    })
+}
+
+fn build_component(
+    type_info: &TypeInfo,
+    fields: Vec<ComponentField>,
+) -> Result<Box<dyn Reflect>, LoadPrefabError> {
+    let invalid = |reason: &str| {
+        LoadPrefabError::InvalidComponentData(type_info.type_name.clone(), reason.to_string())
+    };
+
+    let comp: Box<dyn Reflect> = match type_info.reflect_type {
         ReflectType::Struct => {
             let mut root = DynamicStruct::default();
-            //root.set_name(type_info.registration.type_name().to_string());
+            root.set_name(type_info.registration.type_name().to_string());
             for field in fields {
-                root.insert_boxed(&field.name, field.value);
+                match field {
+                    ComponentField::Named(field) => root.insert_boxed(&field.name, field.value),
+                    _ => return Err(invalid("expected named fields")),
+                }
             }
             Box::new(root)
         }
         ReflectType::TupleStruct => {
             let mut root = DynamicTupleStruct::default();
+            root.set_name(type_info.registration.type_name().to_string());
             for field in fields {
-                root.insert_boxed(field.value);
+                root.insert_boxed(
+                    field
+                        .into_value()
+                        .ok_or_else(|| invalid("expected values"))?,
+                );
             }
             Box::new(root)
         }
         ReflectType::Tuple => {
             let mut root = DynamicTuple::default();
             for field in fields {
-                root.insert_boxed(field.value);
+                root.insert_boxed(
+                    field
+                        .into_value()
+                        .ok_or_else(|| invalid("expected values"))?,
+                );
             }
             Box::new(root)
         }
-        ReflectType::List => todo!(),
-        ReflectType::Map => todo!(),
-        ReflectType::Value => todo!(),
-        ReflectType::Array => todo!(),
-        ReflectType::Enum => todo!(),
+        ReflectType::List => {
+            let mut list = DynamicList::default();
+            list.set_name(type_info.registration.type_name().to_string());
+            for field in fields {
+                list.push_box(
+                    field
+                        .into_value()
+                        .ok_or_else(|| invalid("expected values"))?,
+                );
+            }
+            Box::new(list)
+        }
+        ReflectType::Array => {
+            let values = fields
+                .into_iter()
+                .map(|f| f.into_value().ok_or_else(|| invalid("expected values")))
+                .collect::<Result<Vec<_>, _>>()?;
+            // Applying an array of a different length will panic.
+            if let ReflectTypeInfo::Array(info) = type_info.registration.type_info() {
+                if info.capacity() != values.len() {
+                    return Err(invalid(&format!(
+                        "expected {} values, found {}",
+                        info.capacity(),
+                        values.len()
+                    )));
+                }
+            }
+            let mut array = DynamicArray::new(values.into_boxed_slice());
+            array.set_name(type_info.registration.type_name().to_string());
+            Box::new(array)
+        }
+        ReflectType::Map => {
+            let mut map = DynamicMap::default();
+            map.set_name(type_info.registration.type_name().to_string());
+            for field in fields {
+                match field {
+                    ComponentField::Named(field) => {
+                        map.insert_boxed(Box::new(field.name), field.value);
+                    }
+                    ComponentField::Entry(key, value) => {
+                        if key.reflect_hash().is_none() {
+                            return Err(invalid("map keys must be hashable"));
+                        }
+                        map.insert_boxed(key, value);
+                    }
+                    ComponentField::Unnamed(_) => return Err(invalid("expected key/value pairs")),
+                }
+            }
+            Box::new(map)
+        }
+        ReflectType::Value => {
+            // A value type without any data, ie: `Name`, is initialized to it's default.
+            if fields.is_empty() {
+                return Ok(type_info.default_value.clone_value());
+            }
+            if fields.len() > 1 {
+                return Err(invalid("expected a single value"));
+            }
+            let value = fields
+                .into_iter()
+                .next()
+                .and_then(ComponentField::into_value)
+                .ok_or_else(|| invalid("expected a single value"))?;
+            // Value types can only be applied from the exact same type.
+            if value.type_name() != type_info.registration.type_name() {
+                return Err(invalid(&format!(
+                    "expected a value of type {}, found {}",
+                    type_info.registration.type_name(),
+                    value.type_name()
+                )));
+            }
+            value
+        }
+        ReflectType::Enum => {
+            // An enum component without a variant, ie: `Faction`, is initialized to it's default.
+            if !fields.is_empty() {
+                return Err(LoadPrefabError::MissingEnumVariant(
+                    type_info.type_name.clone(),
+                ));
+            }
+            type_info.default_value.clone_value()
+        }
+    };
+    Ok(comp)
+}
+
+/// Build a value of a type described by a [TypeManifest](crate::TypeManifest). Since the
+/// type itself isn't known, the value is built as the dynamic type of it's kind.
+fn build_described(
+    t: &ManifestType,
+    fields: Vec<ComponentField>,
+) -> Result<Box<dyn Reflect>, LoadPrefabError> {
+    let invalid =
+        |reason: &str| LoadPrefabError::InvalidComponentData(t.name.clone(), reason.to_string());
+    let values = |fields: Vec<ComponentField>| {
+        fields
+            .into_iter()
+            .map(|f| f.into_value().ok_or_else(|| invalid("expected values")))
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let comp: Box<dyn Reflect> = match &t.kind {
+        ManifestKind::Struct { .. } => {
+            let mut root = DynamicStruct::default();
+            root.set_name(t.name.clone());
+            for field in fields {
+                match field {
+                    ComponentField::Named(field) => root.insert_boxed(&field.name, field.value),
+                    _ => return Err(invalid("expected named fields")),
+                }
+            }
+            Box::new(root)
+        }
+        ManifestKind::TupleStruct { .. } => {
+            let mut root = DynamicTupleStruct::default();
+            root.set_name(t.name.clone());
+            for value in values(fields)? {
+                root.insert_boxed(value);
+            }
+            Box::new(root)
+        }
+        ManifestKind::Tuple { .. } => {
+            let mut root = DynamicTuple::default();
+            for value in values(fields)? {
+                root.insert_boxed(value);
+            }
+            Box::new(root)
+        }
+        ManifestKind::List | ManifestKind::Array => {
+            let mut list = DynamicList::default();
+            list.set_name(t.name.clone());
+            for value in values(fields)? {
+                list.push_box(value);
+            }
+            Box::new(list)
+        }
+        ManifestKind::Map => {
+            let mut map = DynamicMap::default();
+            map.set_name(t.name.clone());
+            for field in fields {
+                match field {
+                    ComponentField::Named(field) => {
+                        map.insert_boxed(Box::new(field.name), field.value);
+                    }
+                    ComponentField::Entry(key, value) => {
+                        map.insert_boxed(key, value);
+                    }
+                    ComponentField::Unnamed(_) => return Err(invalid("expected key/value pairs")),
+                }
+            }
+            Box::new(map)
+        }
+        ManifestKind::Enum { variants } => {
+            if !fields.is_empty() {
+                return Err(LoadPrefabError::MissingEnumVariant(t.name.clone()));
+            }
+            // The default variant isn't known, so the first variant stands in for it.
+            let variant = variants
+                .first()
+                .map(|v| v.name.as_str())
+                .unwrap_or_default();
+            Box::new(DynamicEnum::new(
+                t.name.as_str(),
+                variant,
+                DynamicVariant::Unit,
+            ))
+        }
+        ManifestKind::Value => {
+            let mut values = values(fields)?;
+            if values.len() > 1 {
+                return Err(invalid("expected a single value"));
+            }
+            // A value type without any data is left empty, since it's default isn't known.
+            values
+                .pop()
+                .unwrap_or_else(|| Box::new(DynamicStruct::default()))
+        }
+    };
+    Ok(comp)
+}
+
+/// Parse an enum variant, ie: `Faction::Player`, `Faction::Enemy(3)` or
+/// `State::Moving { speed: 2.0 }`.
+///
+/// If the enum type is registered the variant and it's fields are checked against
+/// the type. Since changing an enum's variant requires every field of the new variant,
+/// all fields must be given for tuple and struct variants.
+fn parse_enum(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+    expected: &ExpectedType,
+) -> Result<DynamicEnum, LoadPrefabError> {
+    let span = pair.as_span();
+    read_enum(pair, ctx, expected).map_err(|e| e.at(&span))
+}
+
+fn read_enum(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+    expected: &ExpectedType,
+) -> Result<DynamicEnum, LoadPrefabError> {
+    let mut pairs = pair.into_inner();
+    let type_name = pairs.next().unwrap().as_str();
+    let variant_name = pairs.next().unwrap().as_str();
+
+    // The variant's fields are parsed as the written enum type if it's registered.
+    let expected = match ctx.registry.get_type_data(type_name) {
+        Some(t) => ExpectedType::registered(&expected.path, t),
+        None => match ctx.registry.get_described_type(type_name) {
+            Some(t) => ExpectedType::described(&expected.path, t),
+            None => expected.clone(),
+        },
+    };
+
+    let variant = match pairs.next() {
+        Some(data) if data.as_rule() == Rule::variant_tuple => {
+            let mut tuple = DynamicTuple::default();
+            for (i, value) in data.into_inner().enumerate() {
+                let value_type = expected.variant_element(ctx.registry, variant_name, i);
+                tuple.insert_boxed(parse_value(value, ctx, &value_type)?);
+            }
+            DynamicVariant::Tuple(tuple)
+        }
+        Some(data) => {
+            let path = format!("{}::{}", expected.path, variant_name);
+            check_duplicate_fields(&data, &path)?;
+            let mut data_struct = DynamicStruct::default();
+            for field in data.into_inner() {
+                let mut pairs = field.into_inner();
+                let name_pair = pairs.next().unwrap();
+                let name = name_pair.as_str();
+                expected
+                    .check_variant_field(variant_name, name)
+                    .map_err(|e| e.at(&name_pair.as_span()))?;
+                let value_type = expected.variant_field(ctx.registry, variant_name, name);
+                let value = parse_value(pairs.next().unwrap(), ctx, &value_type)?;
+                data_struct.insert_boxed(name, value);
+            }
+            DynamicVariant::Struct(data_struct)
+        }
+        None => DynamicVariant::Unit,
+    };
+
+    let mut full_name = type_name;
+    if let Some(t) = ctx.registry.get_described_type(type_name) {
+        if matches!(t.kind, ManifestKind::Enum { .. }) {
+            let info = expected.described_variant(variant_name).ok_or_else(|| {
+                LoadPrefabError::UnknownEnumVariant(type_name.to_string(), variant_name.to_string())
+            })?;
+            if !described_variant_matches(info, &variant) {
+                return Err(LoadPrefabError::InvalidEnumVariantFields(
+                    type_name.to_string(),
+                    variant_name.to_string(),
+                ));
+            }
+        }
+    }
+    if let Some(t) = ctx.registry.get_type_data(type_name) {
+        full_name = t.registration.type_name();
+        if let ReflectTypeInfo::Enum(info) = t.registration.type_info() {
+            let info = info.variant(variant_name).ok_or_else(|| {
+                LoadPrefabError::UnknownEnumVariant(type_name.to_string(), variant_name.to_string())
+            })?;
+            if !variant_matches(info, &variant) {
+                return Err(LoadPrefabError::InvalidEnumVariantFields(
+                    type_name.to_string(),
+                    variant_name.to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(DynamicEnum::new(full_name, variant_name, variant))
+}
+
+fn variant_matches(info: &VariantInfo, variant: &DynamicVariant) -> bool {
+    match (info, variant) {
+        (VariantInfo::Unit(_), DynamicVariant::Unit) => true,
+        (VariantInfo::Tuple(info), DynamicVariant::Tuple(tuple)) => {
+            info.field_len() == tuple.field_len()
+        }
+        (VariantInfo::Struct(info), DynamicVariant::Struct(data)) => {
+            info.field_len() == data.field_len()
+                && info.iter().all(|f| data.field(f.name()).is_some())
+        }
+        _ => false,
+    }
+}
+
+fn described_variant_matches(kind: &ManifestVariantKind, variant: &DynamicVariant) -> bool {
+    match (kind, variant) {
+        (ManifestVariantKind::Unit, DynamicVariant::Unit) => true,
+        (ManifestVariantKind::Tuple { fields }, DynamicVariant::Tuple(tuple)) => {
+            fields.len() == tuple.field_len()
+        }
+        (ManifestVariantKind::Struct { fields }, DynamicVariant::Struct(data)) => {
+            fields.len() == data.field_len() && fields.iter().all(|f| data.field(&f.name).is_some())
+        }
+        _ => false,
     }
 }
 
 fn parse_field(
     field: Pair<Rule>,
-    registry: &PrefabRegistry,
+    ctx: &ParseContext,
+    parent: &ExpectedType,
 ) -> Result<ReflectField, LoadPrefabError> {
     let mut field = field.into_inner();
-    let field_name = field.next().unwrap().as_str();
-    let value = parse_value(field.next().unwrap(), registry)?;
+    let name = field.next().unwrap();
+    let field_name = name.as_str();
+    parent
+        .check_field(field_name)
+        .map_err(|e| e.at(&name.as_span()))?;
+    let expected = parent.field(ctx.registry, field_name);
+    let value = parse_value(field.next().unwrap(), ctx, &expected)?;
 
     Ok(ReflectField {
         name: field_name.to_string(),
@@ -172,105 +1426,171 @@ fn parse_field(
     })
 }
 
+/// Parse a value, converting it to the expected type if it's known.
 fn parse_value(
     pair: Pair<Rule>,
-    registry: &PrefabRegistry,
+    ctx: &ParseContext,
+    expected: &ExpectedType,
+) -> Result<Box<dyn Reflect>, LoadPrefabError> {
+    let span = pair.as_span();
+    read_value(pair, ctx, expected).map_err(|e| e.at(&span))
+}
+
+fn read_value(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+    expected: &ExpectedType,
 ) -> Result<Box<dyn Reflect>, LoadPrefabError> {
     let value_string = pair.as_str();
-    match pair.as_rule() {
-        Rule::int => {
-            let num = value_string.parse::<i32>().map_err(|_| {
-                LoadPrefabError::ValueParseError("i32".to_string(), value_string.to_string())
-            })?;
-            Ok(Box::new(num))
-        }
-        Rule::float => {
-            let f = value_string.parse::<f32>().map_err(|_| {
-                LoadPrefabError::ValueParseError("float".to_string(), value_string.to_string())
-            })?;
-            Ok(Box::new(f))
-        }
+    let value: Box<dyn Reflect> = match pair.as_rule() {
+        Rule::int => parse_int(value_string, expected)?,
+        Rule::float => parse_float(value_string, expected)?,
+        Rule::bool => Box::new(value_string == "true"),
         Rule::char => {
-            let ch = value_string.chars().nth(1).ok_or_else(|| {
+            let ch = unescape(value_string).chars().next().ok_or_else(|| {
                 LoadPrefabError::ValueParseError("char".to_string(), value_string.to_string())
             })?;
-            Ok(Box::new(ch as u8))
+            match expected.primitive() {
+                Some("char") => Box::new(ch),
+                _ => Box::new(u8::try_from(ch).map_err(|_| expected.mismatch(value_string))?),
+            }
         }
         Rule::string => {
             let str = parse_string(pair);
-            Ok(Box::new(str))
+            Box::new(str)
         }
-        Rule::component => match parse_component(pair, registry) {
-            Ok(c) => Ok(c.reflect),
-            Err(error) => Err(error),
-        },
+        Rule::enum_value => Box::new(parse_enum(pair, ctx, expected)?),
+        Rule::some => {
+            let mut tuple = DynamicTuple::default();
+            let value_type = expected.variant_element(ctx.registry, "Some", 0);
+            let value = parse_value(pair.into_inner().next().unwrap(), ctx, &value_type)?;
+            tuple.insert_boxed(value);
+            Box::new(DynamicEnum::new("Option", "Some", tuple))
+        }
+        Rule::none => Box::new(DynamicEnum::new("Option", "None", DynamicVariant::Unit)),
+        Rule::entity_ref => {
+            let name = &value_string[1..];
+            let entity_type = std::any::type_name::<Entity>();
+            if matches!(expected.type_name, Some(t) if t != entity_type) {
+                return Err(expected.mismatch(value_string));
+            }
+            let error = LoadPrefabError::UnknownEntityName(name.to_string()).at(&pair.as_span());
+            let index = ctx
+                .reference_entity(name, error)
+                .ok_or_else(|| LoadPrefabError::MisplacedEntityReference(name.to_string()))?;
+            Box::new(PrefabComponent::entity_placeholder(index))
+        }
+        Rule::param_ref => {
+            let name = &value_string[1..];
+            let param = ctx
+                .param(name)
+                .ok_or_else(|| LoadPrefabError::UnknownParam(name.to_string()))?;
+            expected.check(&*param.default, value_string)?;
+            ctx.use_param(name);
+            // The parameter's value is only known once the prefab is inserted.
+            return Ok(Box::new(ParamRef(name.to_string())));
+        }
+        Rule::component => parse_component(pair, ctx, &expected.path)?.reflect,
         Rule::array => {
-            let mut list = DynamicList::default();
-
+            let mut values = Vec::new();
             for value in pair.into_inner() {
-                let array_val = parse_value(value, registry)?;
-                list.push_box(array_val);
+                let value_type = expected.element(ctx.registry, values.len());
+                values.push(parse_value(value, ctx, &value_type)?);
             }
 
-            Ok(Box::new(list))
+            match expected.info {
+                Some(ReflectTypeInfo::Array(info)) => {
+                    // Applying an array of a different length will panic.
+                    if info.capacity() != values.len() {
+                        return Err(expected.mismatch(value_string));
+                    }
+                    Box::new(DynamicArray::new(values.into_boxed_slice()))
+                }
+                _ => {
+                    let mut list = DynamicList::default();
+                    for value in values {
+                        list.push_box(value);
+                    }
+                    Box::new(list)
+                }
+            }
         }
         Rule::range => {
-            let i0 = value_string.find("..").unwrap();
-            let i1 = value_string.rfind("..").unwrap() + 2;
-
-            let start = &value_string[1..i0].parse::<i32>().map_err(|_| {
-                LoadPrefabError::ValueParseError("range min".to_string(), value_string.to_string())
+            // The bounds are parsed as the range's element type, or `i32` if it isn't known.
+            let element = expected.range_element();
+            let mut bounds = pair.into_inner();
+            let start = parse_int(bounds.next().unwrap().as_str(), &element)?;
+            let end = parse_int(bounds.next().unwrap().as_str(), &element)?;
+            let range = int_range(&*start, &*end).ok_or_else(|| {
+                LoadPrefabError::ValueParseError("range".to_string(), value_string.to_string())
             })?;
-            let end = &value_string[i1..value_string.len() - 1]
-                .parse::<i32>()
-                .map_err(|_| {
-                    LoadPrefabError::ValueParseError(
-                        "range max".to_string(),
-                        value_string.to_string(),
-                    )
-                })?;
-
-            Ok(Box::new(Range::<i32> {
-                start: *start,
-                end: *end,
-            }))
+            if matches!(expected.type_name, Some(t) if t != range.type_name()) {
+                return Err(expected.mismatch(value_string));
+            }
+            range
+        }
+        Rule::vec2 => {
+            check_duplicate_fields(&pair, &expected.path)?;
+            let mut v = Vec2::default();
+            for field in pair.into_inner() {
+                let field = parse_f32_field(field, ctx, &expected.path, "Vec2", &["x", "y"])?;
+                match field.0.as_str() {
+                    "x" => v.x = field.1,
+                    "y" => v.y = field.1,
+                    _ => unreachable!(),
+                };
+            }
+            Box::new(v)
         }
         Rule::vec3 => {
+            check_duplicate_fields(&pair, &expected.path)?;
             let mut v = Vec3::default();
             for field in pair.into_inner() {
-                let field = parse_field(field, registry).unwrap();
-                let name = field.name;
-                let val = field.value.cast_ref::<f32>();
-                match name.as_str() {
-                    "x" => v.x = *val,
-                    "y" => v.y = *val,
-                    "z" => v.z = *val,
-                    _ => {} // Error here?
+                let fields = ["x", "y", "z"];
+                let field = parse_f32_field(field, ctx, &expected.path, "Vec3", &fields)?;
+                match field.0.as_str() {
+                    "x" => v.x = field.1,
+                    "y" => v.y = field.1,
+                    "z" => v.z = field.1,
+                    _ => unreachable!(),
                 };
             }
-            Ok(Box::new(v))
+            Box::new(v)
+        }
+        Rule::quat => {
+            check_duplicate_fields(&pair, &expected.path)?;
+            let mut q = Quat::IDENTITY.to_array();
+            for field in pair.into_inner() {
+                let fields = ["x", "y", "z", "w"];
+                let field = parse_f32_field(field, ctx, &expected.path, "Quat", &fields)?;
+                let i = fields.iter().position(|f| *f == field.0).unwrap();
+                q[i] = field.1;
+            }
+            Box::new(Quat::from_array(q))
         }
         Rule::color => {
+            check_duplicate_fields(&pair, &expected.path)?;
             let mut col = Color::default();
             for pair in pair.into_inner() {
                 match pair.as_rule() {
                     Rule::field => {
-                        let field = parse_field(pair, registry).unwrap();
-                        let val = field.value.cast_ref::<f32>();
-                        match field.name.as_str() {
+                        let fields = ["r", "g", "b", "a"];
+                        let (name, val) =
+                            parse_f32_field(pair, ctx, &expected.path, "Color", &fields)?;
+                        match name.as_str() {
                             "r" => {
-                                col.set_r(*val);
+                                col.set_r(val);
                             }
                             "g" => {
-                                col.set_g(*val);
+                                col.set_g(val);
                             }
                             "b" => {
-                                col.set_b(*val);
+                                col.set_b(val);
                             }
                             "a" => {
-                                col.set_a(*val);
+                                col.set_a(val);
                             }
-                            _ => {}
+                            _ => unreachable!(),
                         };
                     }
                     Rule::color_value => {
@@ -289,43 +1609,197 @@ fn parse_value(
                     _ => unreachable!(),
                 }
             }
-            Ok(Box::new(col))
+            Box::new(col)
         }
         Rule::shape => {
             let shape = pair.into_inner().next().unwrap().as_str();
-            Ok(Box::new(shape.to_string()))
+            Box::new(shape.to_string())
         }
         _ => {
             let str = format!("{:#?}", pair.as_rule());
-            Err(LoadPrefabError::UnhandledValueRule(str))
+            return Err(LoadPrefabError::UnhandledValueRule(str));
+        }
+    };
+
+    expected.check(&*value, value_string)?;
+    Ok(value)
+}
+
+/// Build a range from it's bounds, if they're integers of the same type.
+fn int_range(start: &dyn Reflect, end: &dyn Reflect) -> Option<Box<dyn Reflect>> {
+    macro_rules! range {
+        ($($t:ty),*) => {
+            $(
+                if let (Some(start), Some(end)) = (start.downcast_ref::<$t>(), end.downcast_ref::<$t>()) {
+                    return Some(Box::new(Range { start: *start, end: *end }));
+                }
+            )*
+        };
+    }
+    range!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+    None
+}
+
+/// Ensure no field is given more than once in a component, enum variant, build command or
+/// built in value.
+fn check_duplicate_fields(pair: &Pair<Rule>, path: &str) -> Result<(), LoadPrefabError> {
+    let mut names = Vec::new();
+    for field in pair.clone().into_inner() {
+        if field.as_rule() != Rule::field {
+            continue;
+        }
+        let name = field.into_inner().next().unwrap();
+        if names.contains(&name.as_str()) {
+            let path = format!("{}.{}", path, name.as_str());
+            return Err(LoadPrefabError::DuplicateField(path).at(&name.as_span()));
         }
+        names.push(name.as_str());
+    }
+    Ok(())
+}
+
+/// Parse a field of a built in vector or color type. `fields` are the valid field names.
+fn parse_f32_field(
+    field: Pair<Rule>,
+    ctx: &ParseContext,
+    path: &str,
+    type_name: &str,
+    fields: &[&str],
+) -> Result<(String, f32), LoadPrefabError> {
+    let mut field = field.into_inner();
+    let name_pair = field.next().unwrap();
+    let name = name_pair.as_str();
+    check_field_name(type_name, name, fields).map_err(|e| e.at(&name_pair.as_span()))?;
+    let expected = ExpectedType::named(format!("{}.{}", path, name), "f32");
+    let value = parse_value(field.next().unwrap(), ctx, &expected)?;
+    if let Some(param) = value.downcast_ref::<ParamRef>() {
+        return Err(LoadPrefabError::MisplacedParam(param.0.clone()));
     }
+    Ok((name.to_string(), *value.cast_ref::<f32>()))
 }
 
 fn parse_string(pair: Pair<Rule>) -> String {
-    let str = pair.as_str();
-    str[1..str.len().saturating_sub(1)].to_string()
+    unescape(pair.as_str())
+}
+
+/// The text of a quoted string or char with the quotes removed and escape sequences, ie:
+/// `\"` or `\n`, replaced with the characters they stand for.
+fn unescape(quoted: &str) -> String {
+    let mut out = String::new();
+    let mut chars = quoted[1..quoted.len().saturating_sub(1)].chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some(c) => c,
+                None => break,
+            },
+            c => c,
+        };
+        out.push(c);
+    }
+    out
 }
 
 fn parse_command(
     pair: Pair<Rule>,
-    registry: &PrefabRegistry,
+    ctx: &ParseContext,
 ) -> Result<PrefabCommandData, LoadPrefabError> {
-    let mut pairs = pair.into_inner();
-    let command_name = pairs.next().unwrap().as_str().to_string();
+    let span = pair.as_span();
+    read_command(pair, ctx).map_err(|e| e.at(&span))
+}
+
+fn read_command(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+) -> Result<PrefabCommandData, LoadPrefabError> {
+    let mut pairs = pair.clone().into_inner();
+    let name = pairs.next().unwrap();
+    let command_name = name.as_str().to_string();
+
+    let declared = ctx.registry.command_properties(&command_name);
+    let registered = declared.is_some();
+    let unregistered =
+        || LoadPrefabError::UnregisteredBuildCommand(command_name.clone()).at(&name.as_span());
+    // In diagnostic mode the properties of an unregistered command are still parsed,
+    // so errors inside them are reported too.
+    if !registered && !ctx.is_diagnostic() {
+        return Err(unregistered());
+    }
 
     let mut properties = None;
+    ctx.track_params();
+    ctx.recover(check_duplicate_fields(&pair, &command_name))?;
 
+    let declared = declared.unwrap_or_default();
     for field in pairs {
-        let field = parse_field(field, registry)?;
+        let field = match ctx.recover(parse_property(field, ctx, &command_name, &declared))? {
+            Some(field) => field,
+            None => continue,
+        };
         let props = properties.get_or_insert(DynamicStruct::default());
 
         props.insert_boxed(field.name.as_str(), field.value);
     }
 
+    if !registered {
+        return Err(unregistered());
+    }
+
     Ok(PrefabCommandData {
         name: command_name,
         properties,
+        uses_params: ctx.used_param().is_some(),
+    })
+}
+
+/// Parse a build command property as the type the command declares for it. Commands that
+/// don't declare their properties accept any property, parsed as it's default type.
+fn parse_property(
+    field: Pair<Rule>,
+    ctx: &ParseContext,
+    command: &str,
+    declared: &[(&str, &str)],
+) -> Result<ReflectField, LoadPrefabError> {
+    let mut field = field.into_inner();
+    let name = field.next().unwrap();
+    let field_name = name.as_str();
+    let path = format!("{}.{}", command, field_name);
+    let value = field.next().unwrap();
+
+    if declared.is_empty() {
+        let value = parse_value(value, ctx, &ExpectedType::unknown(path))?;
+        let name = field_name.to_string();
+        return Ok(ReflectField { name, value });
+    }
+
+    let names: Vec<_> = declared.iter().map(|(name, _)| *name).collect();
+    check_field_name(command, field_name, &names).map_err(|e| e.at(&name.as_span()))?;
+    let type_name = declared.iter().find(|(n, _)| *n == field_name).unwrap().1;
+
+    // Properties that accept more than one type list them separated by `|`. Types the
+    // registry doesn't know, ie: `Shape`, are parsed as their default type.
+    let alternatives: Option<Vec<_>> = type_name
+        .split('|')
+        .map(|t| ExpectedType::from_name(ctx.registry, path.clone(), t.trim()))
+        .collect();
+    let value = match alternatives.as_deref() {
+        Some([expected]) => parse_value(value, ctx, expected)?,
+        Some(alternatives) => alternatives
+            .iter()
+            .find_map(|expected| parse_value(value.clone(), ctx, expected).ok())
+            .ok_or_else(|| {
+                let text = value.as_str().to_string();
+                LoadPrefabError::MismatchedValueType(path, type_name.to_string(), text)
+                    .at(&value.as_span())
+            })?,
+        None => parse_value(value, ctx, &ExpectedType::unknown(path))?,
+    };
+    Ok(ReflectField {
+        name: field_name.to_string(),
+        value,
     })
 }
 
@@ -335,17 +1809,30 @@ mod test {
 
     use pest::Parser;
 
+    use crate::diagnostic::{ParseContext, Severity};
     use crate::dynamic_cast::*;
-    use crate::parse::parse_prefab;
+    use crate::parse::{diagnose_prefab_string, parse_prefab, parse_prefab_file};
     use crate::prefab::PrefabBuildStep;
     use crate::registry::PrefabRegistry;
+    use crate::value_type::ExpectedType;
     use crate::{
         dynamic_cast::GetValue,
-        parse::{parse_component, parse_value, PrefabParser, Rule},
+        parse::{parse_component, parse_value, LoadPrefabError, PrefabParser, Rule},
     };
 
     use super::{parse_command, parse_field, parse_string};
 
+    /// A build command that does nothing, for tests that parse commands.
+    #[derive(Default)]
+    struct DoStuff;
+    impl crate::build_commands::BuildPrefabCommand for DoStuff {
+        fn run(&self, _: Option<&bevy::reflect::DynamicStruct>, _: &mut World, _: Entity) {}
+
+        fn key(&self) -> &str {
+            "DOSTUFF"
+        }
+    }
+
     #[test]
     fn command_parse() {
         let input = "DOSTUFF!(i: 10, pet: Animal {name: \"Polly\"})";
@@ -364,8 +1851,9 @@ mod test {
         }
 
         registry.register_type::<Animal>();
+        registry.register_build_command::<DoStuff>();
 
-        let parsed = parse_command(parse, &registry).unwrap();
+        let parsed = parse_command(parse, &ParseContext::new(&registry)).unwrap();
 
         let props = parsed.properties.unwrap();
 
@@ -376,29 +1864,89 @@ mod test {
         assert_eq!(pet.name, "Polly".to_string());
     }
 
+    #[derive(Default)]
+    struct Resize;
+    impl crate::build_commands::BuildPrefabCommand for Resize {
+        fn run(&self, _: Option<&bevy::reflect::DynamicStruct>, _: &mut World, _: Entity) {}
+
+        fn key(&self) -> &str {
+            "Resize"
+        }
+
+        fn properties(&self) -> &[(&str, &str)] {
+            &[("scale", "u8"), ("size", "f32 | Vec2"), ("shape", "Shape")]
+        }
+    }
+
+    #[test]
+    fn typed_command_properties() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_build_command::<Resize>();
+
+        let parse = |input| {
+            let parsed = PrefabParser::parse(Rule::command, input)
+                .unwrap()
+                .next()
+                .unwrap();
+            parse_command(parsed, &ParseContext::new(&registry))
+        };
+        let properties = |input| parse(input).unwrap().properties.unwrap();
+
+        assert_eq!(properties("Resize!(scale: 3)").get::<u8>("scale"), 3);
+        assert_eq!(properties("Resize!(size: 2.0)").get::<f32>("size"), 2.0);
+        let size = properties("Resize!(size: Vec2 { x: 2.0 })").get::<Vec2>("size");
+        assert_eq!(size, Vec2::new(2.0, 0.0));
+        let shape = properties("Resize!(shape: shape::Cube)").get::<String>("shape");
+        assert_eq!(shape, "Cube");
+
+        match parse("Resize!(size: \"big\")").unwrap_err().kind() {
+            LoadPrefabError::MismatchedValueType(path, type_name, _) => {
+                assert_eq!(
+                    (path.as_str(), type_name.as_str()),
+                    ("Resize.size", "f32 | Vec2")
+                );
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        assert!(matches!(
+            parse("Resize!(scale: 300)").unwrap_err().kind(),
+            LoadPrefabError::ValueParseError(_, _) | LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        assert!(matches!(
+            parse("Resize!(scal: 3)").unwrap_err().kind(),
+            LoadPrefabError::UnknownField { suggestion: Some(s), .. } if s == "scale"
+        ));
+    }
+
     #[test]
     fn prefab_parse() {
-        let input = "SomeName { dosomething!(), Visibility }";
+        let input = "SomeName { DOSTUFF!(), Visibility }";
         let mut parsed = PrefabParser::parse(Rule::prefab, input).unwrap();
         let mut registry = PrefabRegistry::default();
         registry.register_type::<Visibility>();
+        registry.register_build_command::<DoStuff>();
 
-        let prefab = parse_prefab(parsed.next().unwrap(), &registry).unwrap();
+        let prefab = parse_prefab(
+            parsed.next().unwrap(),
+            &ParseContext::new(&registry),
+            &Default::default(),
+        )
+        .unwrap();
 
         assert_eq!(prefab.name, Some("SomeName".to_string()));
 
         match &prefab.steps[0] {
-            PrefabBuildStep::AddComponent(_) => unreachable!(),
             PrefabBuildStep::RunCommand(command) => {
-                assert_eq!(command.name, "dosomething");
+                assert_eq!(command.name, "DOSTUFF");
             }
+            _ => unreachable!(),
         }
 
         match &prefab.steps[1] {
             PrefabBuildStep::AddComponent(comp) => {
                 assert_eq!(comp.type_name, "Visibility");
             }
-            PrefabBuildStep::RunCommand(_) => unreachable!(),
+            _ => unreachable!(),
         }
     }
 
@@ -410,10 +1958,22 @@ mod test {
             .next()
             .unwrap();
         let registry = PrefabRegistry::default();
-        let parsed = parse_value(parse, &registry);
+        let parsed = parse_value(
+            parse,
+            &ParseContext::new(&registry),
+            &ExpectedType::default(),
+        );
         assert!(parsed.is_ok());
         let val = *parsed.unwrap().downcast::<u8>().unwrap();
         assert_eq!(val as char, 'a');
+
+        let parse = PrefabParser::parse(Rule::value, r"'\''")
+            .unwrap()
+            .next()
+            .unwrap();
+        let expected = ExpectedType::named("c".to_string(), "char");
+        let parsed = parse_value(parse, &ParseContext::new(&registry), &expected).unwrap();
+        assert_eq!(*parsed.cast_ref::<char>(), '\'');
     }
 
     #[test]
@@ -426,7 +1986,11 @@ mod test {
 
         let registry = PrefabRegistry::default();
 
-        let parsed = parse_value(parse, &registry);
+        let parsed = parse_value(
+            parse,
+            &ParseContext::new(&registry),
+            &ExpectedType::default(),
+        );
         let val = *parsed.unwrap().downcast::<Color>().unwrap();
 
         assert_eq!(Color::RED, val);
@@ -437,7 +2001,11 @@ mod test {
             .next()
             .unwrap();
 
-        let parsed = parse_value(parse, &registry);
+        let parsed = parse_value(
+            parse,
+            &ParseContext::new(&registry),
+            &ExpectedType::default(),
+        );
         let col = *parsed.unwrap().downcast::<Color>().unwrap();
         assert_eq!(1.0, col.r());
         assert_eq!(0.5, col.g());
@@ -458,7 +2026,12 @@ mod test {
 
         let mut v = Vec3::default();
 
-        let dynamic = parse_value(parse, &registry).unwrap();
+        let dynamic = parse_value(
+            parse,
+            &ParseContext::new(&registry),
+            &ExpectedType::default(),
+        )
+        .unwrap();
 
         v.apply(&*dynamic);
 
@@ -466,6 +2039,29 @@ mod test {
         assert_eq!(v.z, 3.0);
     }
 
+    #[test]
+    fn quat_parse() {
+        let registry = PrefabRegistry::default();
+        let parse = |input| {
+            let parsed = PrefabParser::parse(Rule::quat, input)
+                .unwrap()
+                .next()
+                .unwrap();
+            let expected = ExpectedType::default();
+            parse_value(parsed, &ParseContext::new(&registry), &expected)
+        };
+
+        let q = parse("Quat { z: 0.5, w: 0.5 }").unwrap();
+        assert_eq!(*q.cast_ref::<Quat>(), Quat::from_xyzw(0.0, 0.0, 0.5, 0.5));
+        // Omitted fields are taken from the identity rotation.
+        let q = parse("Quat").unwrap();
+        assert_eq!(*q.cast_ref::<Quat>(), Quat::IDENTITY);
+        assert!(matches!(
+            parse("Quat { v: 1.0 }").unwrap_err().kind(),
+            LoadPrefabError::UnknownField { .. }
+        ));
+    }
+
     #[test]
     fn transform_parse() {
         let mut registry = PrefabRegistry::default();
@@ -480,7 +2076,7 @@ mod test {
             .next()
             .unwrap();
 
-        let comp = parse_component(parsed, &registry).unwrap();
+        let comp = parse_component(parsed, &ParseContext::new(&registry), "").unwrap();
 
         let mut transform = Transform::default();
 
@@ -497,6 +2093,13 @@ mod test {
         let str = parse_string(parsed.next().unwrap());
 
         assert_eq!("Hello", str);
+
+        let input = r#""Sir \"Lance\" of\tCamelot\\\n""#;
+        let mut parsed = PrefabParser::parse(Rule::string, input).unwrap();
+        let str = parse_string(parsed.next().unwrap());
+
+        assert_eq!("Sir \"Lance\" of\tCamelot\\\n", str);
+        assert!(PrefabParser::parse(Rule::string, r#""\q""#).is_err());
     }
 
     #[test]
@@ -505,9 +2108,836 @@ mod test {
 
         let registry = PrefabRegistry::default();
         let mut parse = PrefabParser::parse(Rule::field, input).unwrap();
-        let field = parse_field(parse.next().unwrap(), &registry).unwrap();
+        let field = parse_field(
+            parse.next().unwrap(),
+            &ParseContext::new(&registry),
+            &ExpectedType::default(),
+        )
+        .unwrap();
 
         assert_eq!("a", field.name);
         assert_eq!("hi", field.value.cast_ref::<String>());
     }
+
+    #[derive(Debug, Default, Reflect, FromReflect, Component, PartialEq)]
+    #[reflect(Component)]
+    enum Faction {
+        #[default]
+        Player,
+        Enemy(i32),
+        Neutral {
+            mood: f32,
+        },
+    }
+
+    #[derive(Debug, Default, Reflect, Component)]
+    #[reflect(Component)]
+    struct Unit {
+        faction: Faction,
+        target: Option<i32>,
+    }
+
+    #[test]
+    fn enum_component_parse() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Faction>();
+
+        let input = "{ Faction::Enemy(3), Faction::Neutral { mood: 0.5 } }";
+        let mut parsed = PrefabParser::parse(Rule::prefab, input).unwrap();
+        let prefab = parse_prefab(
+            parsed.next().unwrap(),
+            &ParseContext::new(&registry),
+            &Default::default(),
+        )
+        .unwrap();
+
+        let mut faction = Faction::default();
+        match &prefab.steps[0] {
+            PrefabBuildStep::AddComponent(comp) => faction.apply(&*comp.reflect),
+            _ => unreachable!(),
+        }
+        assert_eq!(faction, Faction::Enemy(3));
+
+        match &prefab.steps[1] {
+            PrefabBuildStep::AddComponent(comp) => faction.apply(&*comp.reflect),
+            _ => unreachable!(),
+        }
+        assert_eq!(faction, Faction::Neutral { mood: 0.5 });
+    }
+
+    #[test]
+    fn enum_field_parse() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Faction>();
+        registry.register_type::<Unit>();
+
+        let input = "Unit { faction: Faction::Enemy(7), target: Some(5) }";
+        let parsed = PrefabParser::parse(Rule::component, input)
+            .unwrap()
+            .next()
+            .unwrap();
+        let comp = parse_component(parsed, &ParseContext::new(&registry), "").unwrap();
+
+        let mut unit = Unit::default();
+        unit.apply(&*comp.reflect);
+        assert_eq!(unit.faction, Faction::Enemy(7));
+        assert_eq!(unit.target, Some(5));
+
+        let input = "Unit { faction: Faction::Player, target: None }";
+        let parsed = PrefabParser::parse(Rule::component, input)
+            .unwrap()
+            .next()
+            .unwrap();
+        let comp = parse_component(parsed, &ParseContext::new(&registry), "").unwrap();
+
+        unit.apply(&*comp.reflect);
+        assert_eq!(unit.faction, Faction::Player);
+        assert_eq!(unit.target, None);
+    }
+
+    #[test]
+    fn enum_variant_errors() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Faction>();
+
+        let parse = |input| {
+            let mut parsed = PrefabParser::parse(Rule::prefab, input).unwrap();
+            parse_prefab(
+                parsed.next().unwrap(),
+                &ParseContext::new(&registry),
+                &Default::default(),
+            )
+        };
+
+        assert!(matches!(
+            parse("{ Faction::Pirate }").unwrap_err().kind(),
+            LoadPrefabError::UnknownEnumVariant(_, _)
+        ));
+        assert!(matches!(
+            parse("{ Faction::Enemy }").unwrap_err().kind(),
+            LoadPrefabError::InvalidEnumVariantFields(_, _)
+        ));
+        assert!(matches!(
+            parse("{ Faction::Neutral { anger: 1.0 } }")
+                .unwrap_err()
+                .kind(),
+            LoadPrefabError::UnknownField { .. }
+        ));
+        assert!(matches!(
+            parse("{ Faction { mood: 1.0 } }").unwrap_err().kind(),
+            LoadPrefabError::MissingEnumVariant(_)
+        ));
+    }
+
+    #[test]
+    fn container_component_parse() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Vec<i32>>();
+        registry.register_type::<[f32; 3]>();
+        registry.register_type::<bevy::utils::HashMap<String, i32>>();
+        registry.register_type::<String>();
+
+        let parse = |input| {
+            let parsed = PrefabParser::parse(Rule::component, input)
+                .unwrap()
+                .next()
+                .unwrap();
+            parse_component(parsed, &ParseContext::new(&registry), "")
+        };
+
+        let comp = parse("Vec<i32> [1, 2, 3]").unwrap();
+        let mut list = Vec::<i32>::new();
+        list.apply(&*comp.reflect);
+        assert_eq!(list, vec![1, 2, 3]);
+
+        let comp = parse("[f32; 3] [1.0, 2.0, 3.0]").unwrap();
+        let mut array = [0.0f32; 3];
+        array.apply(&*comp.reflect);
+        assert_eq!(array, [1.0, 2.0, 3.0]);
+
+        let comp = parse("HashMap<String,i32> { \"sword\": 1, shield: 2 }").unwrap();
+        let mut map = bevy::utils::HashMap::<String, i32>::default();
+        map.apply(&*comp.reflect);
+        assert_eq!(map.get("sword"), Some(&1));
+        assert_eq!(map.get("shield"), Some(&2));
+
+        let comp = parse("String(\"Polly\")").unwrap();
+        let mut string = String::new();
+        string.apply(&*comp.reflect);
+        assert_eq!(string, "Polly");
+
+        assert!(matches!(
+            parse("[f32; 3] [1.0, 2.0]").unwrap_err().kind(),
+            LoadPrefabError::InvalidComponentData(_, _)
+        ));
+        assert!(matches!(
+            parse("String(10)").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        assert!(matches!(
+            parse("Vec<i32> { \"a\": 1 }").unwrap_err().kind(),
+            LoadPrefabError::InvalidComponentData(_, _)
+        ));
+    }
+
+    #[derive(Debug, Default, Reflect, FromReflect, Component)]
+    #[reflect(Component)]
+    struct Stats {
+        level: u8,
+        gold: u32,
+        xp: i64,
+        speed: f64,
+        items: Vec<usize>,
+        bonus: Option<u16>,
+        title: String,
+        initial: u8,
+        range: std::ops::Range<u8>,
+    }
+
+    #[test]
+    fn typed_field_parse() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Stats>();
+
+        let input = "Stats {
+            level: 5, gold: 4000000000, xp: -9000000000, speed: 2,
+            items: [1, 2], bonus: Some(7), title: \"Hero\", initial: 'H', range: (1..5),
+        }";
+        let parsed = PrefabParser::parse(Rule::component, input)
+            .unwrap()
+            .next()
+            .unwrap();
+        let comp = parse_component(parsed, &ParseContext::new(&registry), "").unwrap();
+
+        let mut stats = Stats::default();
+        stats.apply(&*comp.reflect);
+
+        assert_eq!(stats.level, 5);
+        assert_eq!(stats.gold, 4000000000);
+        assert_eq!(stats.xp, -9000000000);
+        assert_eq!(stats.speed, 2.0);
+        assert_eq!(stats.items, vec![1, 2]);
+        assert_eq!(stats.bonus, Some(7));
+        assert_eq!(stats.title, "Hero");
+        assert_eq!(stats.initial, b'H');
+        assert_eq!(stats.range, 1..5);
+    }
+
+    #[test]
+    fn typed_field_errors() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Stats>();
+
+        let parse = |input| {
+            let parsed = PrefabParser::parse(Rule::component, input)
+                .unwrap()
+                .next()
+                .unwrap();
+            parse_component(parsed, &ParseContext::new(&registry), "")
+        };
+
+        match parse("Stats { level: 300 }").unwrap_err().kind() {
+            LoadPrefabError::MismatchedValueType(path, expected, found) => {
+                assert_eq!(path, "Stats.level");
+                assert_eq!(expected, "u8");
+                assert_eq!(found, "300");
+            }
+            _ => unreachable!(),
+        }
+        match parse("Stats { items: [1, -2] }").unwrap_err().kind() {
+            LoadPrefabError::MismatchedValueType(path, _, _) => {
+                assert_eq!(path, "Stats.items[1]");
+            }
+            _ => unreachable!(),
+        }
+        assert!(matches!(
+            parse("Stats { title: 10 }").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        assert!(matches!(
+            parse("Stats { gold: 1.5 }").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        // Chars that don't fit in a byte.
+        assert!(matches!(
+            parse("Stats { initial: 'ж' }").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        match parse("Stats { range: (1..300) }").unwrap_err().kind() {
+            LoadPrefabError::MismatchedValueType(path, expected, _) => {
+                assert_eq!(path, "Stats.range");
+                assert_eq!(expected, "u8");
+            }
+            _ => unreachable!(),
+        }
+        assert!(matches!(
+            parse("Stats { range: (1u16..5u16) }").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+    }
+
+    #[test]
+    fn field_name_errors() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Vec3>();
+        registry.register_type::<Transform>();
+
+        let parse = |input| {
+            let parsed = PrefabParser::parse(Rule::component, input)
+                .unwrap()
+                .next()
+                .unwrap();
+            parse_component(parsed, &ParseContext::new(&registry), "")
+        };
+
+        let error = parse("Transform { translaton: Vec3 { x: 1.0 } }").unwrap_err();
+        match error.kind() {
+            LoadPrefabError::UnknownField {
+                type_name,
+                field,
+                suggestion,
+                valid,
+            } => {
+                assert_eq!(type_name, "Transform");
+                assert_eq!(field, "translaton");
+                assert_eq!(suggestion.as_deref(), Some("translation"));
+                assert_eq!(valid, &["translation", "rotation", "scale"]);
+            }
+            _ => unreachable!(),
+        }
+        assert!(error.to_string().contains("Did you mean 'translation'?"));
+        assert_eq!(error.location().unwrap().column, 13);
+
+        // Nested fields are checked against their own type.
+        match parse("Transform { translation: Vec3 { x: 1.0, w: 2.0 } }")
+            .unwrap_err()
+            .kind()
+        {
+            LoadPrefabError::UnknownField {
+                type_name,
+                suggestion,
+                ..
+            } => {
+                assert_eq!(type_name, "Vec3");
+                assert_eq!(suggestion, &None);
+            }
+            _ => unreachable!(),
+        }
+        assert!(parse("Transform { scale: Vec3 { x: 1.0, y: 2.0, z: 3.0 } }").is_ok());
+    }
+
+    #[test]
+    fn literal_parse() {
+        let registry = PrefabRegistry::default();
+        let parse = |input| {
+            let parse = PrefabParser::parse(Rule::value, input)
+                .unwrap()
+                .next()
+                .unwrap();
+            parse_value(
+                parse,
+                &ParseContext::new(&registry),
+                &ExpectedType::default(),
+            )
+            .unwrap()
+        };
+
+        assert!(*parse("true").cast_ref::<bool>());
+        assert!(!*parse("false").cast_ref::<bool>());
+        assert_eq!(*parse("0xFF").cast_ref::<i32>(), 255);
+        assert_eq!(*parse("-0x10").cast_ref::<i32>(), -16);
+        assert_eq!(*parse("0b101").cast_ref::<i32>(), 5);
+        assert_eq!(*parse("10_000").cast_ref::<i32>(), 10000);
+        assert_eq!(*parse("5u8").cast_ref::<u8>(), 5);
+        assert_eq!(*parse("0xFFu8").cast_ref::<u8>(), 255);
+        assert_eq!(*parse("0x1f32").cast_ref::<i32>(), 0x1f32);
+        assert_eq!(*parse("-3i64").cast_ref::<i64>(), -3);
+        assert_eq!(*parse("2f64").cast_ref::<f64>(), 2.0);
+        assert_eq!(*parse("2.0f64").cast_ref::<f64>(), 2.0);
+        assert_eq!(*parse("1_000.5").cast_ref::<f32>(), 1000.5);
+    }
+
+    #[test]
+    fn literal_errors() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Stats>();
+
+        let parse = |input| {
+            let parsed = PrefabParser::parse(Rule::component, input)
+                .unwrap()
+                .next()
+                .unwrap();
+            parse_component(parsed, &ParseContext::new(&registry), "")
+        };
+
+        assert!(parse("Stats { level: 0xFF }").is_ok());
+        assert!(matches!(
+            parse("Stats { level: 300u16 }").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        assert!(matches!(
+            parse("Stats { speed: 2.0f32 }").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        assert!(matches!(
+            parse("Stats { title: true }").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+    }
+
+    #[test]
+    fn error_location() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Stats>();
+
+        let input = "{\n    Stats {\n        level: 300,\n    },\n}";
+        let error = parse_prefab_file("stats.prefab", input, &registry).unwrap_err();
+
+        assert!(matches!(
+            error.kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        let location = error.location().unwrap();
+        assert_eq!(location.path.as_deref(), Some("stats.prefab"));
+        assert_eq!(location.line, 3);
+        assert_eq!(location.column, 16);
+        assert_eq!(location.snippet, "        level: 300,\n               ^-^");
+        assert!(error.to_string().contains("--> stats.prefab:3:16"));
+
+        let input = "{\n    Stats {\n        level 3,\n    },\n}";
+        let error = parse_prefab_file("stats.prefab", input, &registry).unwrap_err();
+        assert!(matches!(error.kind(), LoadPrefabError::PestParseError(_)));
+        assert_eq!(error.location().unwrap().line, 3);
+    }
+
+    #[test]
+    fn diagnose_all_errors() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Stats>();
+
+        let input = "{
+            Stats { level: 300, gold: -1, title: \"Orc\" },
+            Missing { x: 1 },
+            Spawn!(count: 3),
+            Stats { level: 3, level: 4 },
+        }";
+        let diagnostics = diagnose_prefab_string("test.prefab", input, &registry);
+
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.location().unwrap().line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Severity::Error, 2),
+                (Severity::Error, 2),
+                (Severity::Error, 3),
+                (Severity::Error, 4),
+                (Severity::Error, 5),
+            ]
+        );
+        assert!(matches!(
+            diagnostics[2].error.kind(),
+            LoadPrefabError::UnregisteredPrefabComponent(_)
+        ));
+        assert!(matches!(
+            diagnostics[3].error.kind(),
+            LoadPrefabError::UnregisteredBuildCommand(_)
+        ));
+        assert!(matches!(
+            diagnostics[4].error.kind(),
+            LoadPrefabError::DuplicateField(_)
+        ));
+
+        // Without errors parsing still succeeds.
+        let diagnostics =
+            diagnose_prefab_string("test.prefab", "{ Stats { level: 3 } }", &registry);
+        assert!(diagnostics.is_empty());
+        let diagnostics = diagnose_prefab_string("test.prefab", "{ Stats { level 3 } }", &registry);
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn component_and_command_errors() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Vec3>();
+        registry.register_type::<Transform>();
+
+        let parse = |input| {
+            let mut parsed = PrefabParser::parse(Rule::prefab, input).unwrap();
+            parse_prefab(
+                parsed.next().unwrap(),
+                &ParseContext::new(&registry),
+                &Default::default(),
+            )
+        };
+
+        assert!(matches!(
+            parse("{ Vec3 { x: 1.0 } }").unwrap_err().kind(),
+            LoadPrefabError::MissingReflectComponent(_)
+        ));
+        assert!(matches!(
+            parse("{ DOSTUFF!() }").unwrap_err().kind(),
+            LoadPrefabError::UnregisteredBuildCommand(_)
+        ));
+        // Nested components don't need to be reflected components.
+        assert!(parse("{ Transform { scale: Vec3 { x: 1.0 } } }").is_ok());
+    }
+
+    #[test]
+    fn duplicate_field_errors() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Transform>();
+        registry.register_type::<Faction>();
+        registry.register_build_command::<DoStuff>();
+
+        let duplicate = |input| match parse_prefab_file("test.prefab", input, &registry) {
+            Err(e) => match e.kind() {
+                LoadPrefabError::DuplicateField(path) => path.clone(),
+                e => panic!("unexpected error {}", e),
+            },
+            Ok(_) => panic!("duplicate field was accepted"),
+        };
+
+        let input = "{ Transform { scale: Vec3 { x: 1.0 }, scale: Vec3 { x: 2.0 } } }";
+        assert_eq!(duplicate(input), "Transform.scale");
+        let input = "{ Transform { translation: Vec3 { x: 1.0, x: 2.0 } } }";
+        assert_eq!(duplicate(input), "Transform.translation.x");
+        let input = "{ Faction::Neutral { mood: 1.0, mood: 2.0 } }";
+        assert_eq!(duplicate(input), "Faction::Neutral.mood");
+        assert_eq!(
+            duplicate("{ DOSTUFF!(count: 1, count: 2) }"),
+            "DOSTUFF.count"
+        );
+    }
+
+    #[derive(Debug, Default, Reflect, FromReflect)]
+    struct Damage {
+        amount: i32,
+        kind: String,
+    }
+
+    #[derive(Debug, Default, Reflect, Component)]
+    #[reflect(Component)]
+    struct Weapon {
+        name: String,
+        damage: Damage,
+    }
+
+    #[test]
+    fn inherit_prefab() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Weapon>();
+        registry.register_type::<Damage>();
+        registry.register_type::<Visibility>();
+        registry.clear_sources();
+        registry.add_source(crate::MemoryPrefabSource::from_iter([
+            (
+                "sword.prefab",
+                "Sword { Weapon { name: \"Sword\", damage: Damage { amount: 5, kind: \"slash\" } } }",
+            ),
+            ("a.prefab", "A : \"b.prefab\" { Visibility }"),
+            ("b.prefab", "B : \"a.prefab\" { Visibility }"),
+        ]));
+
+        let input = "SharpSword : \"sword.prefab\" {
+            Visibility,
+            Weapon { damage: Damage { amount: 10 } },
+        }";
+        let prefab = parse_prefab_file("sharp_sword.prefab", input, &registry).unwrap();
+        assert_eq!(prefab.name.as_deref(), Some("SharpSword"));
+        assert_eq!(prefab.base.as_deref(), Some("sword.prefab"));
+        assert_eq!(prefab.steps.len(), 2);
+
+        // The base prefab's fields are merged into the component.
+        let mut weapon = Weapon::default();
+        match &prefab.steps[0] {
+            PrefabBuildStep::AddComponent(comp) => weapon.apply(&*comp.reflect),
+            _ => unreachable!(),
+        }
+        assert_eq!(weapon.name, "Sword");
+        assert_eq!(weapon.damage.amount, 10);
+        assert_eq!(weapon.damage.kind, "slash");
+
+        let error =
+            parse_prefab_file("c.prefab", ": \"a.prefab\" { Visibility }", &registry).unwrap_err();
+        match error.kind() {
+            LoadPrefabError::Cycle(chain) => {
+                assert_eq!(chain, &["a.prefab", "b.prefab", "a.prefab"])
+            }
+            e => panic!("unexpected error {}", e),
+        }
+
+        let error = parse_prefab_file("d.prefab", ": \"missing.prefab\" { Visibility }", &registry)
+            .unwrap_err();
+        assert!(matches!(error.kind(), LoadPrefabError::FileReadError(_, _)));
+        assert_eq!(error.location().unwrap().path.as_deref(), Some("d.prefab"));
+    }
+
+    #[derive(Debug, Default, Reflect, FromReflect)]
+    struct Power(Damage, u8);
+
+    #[derive(Debug, Default, Reflect, FromReflect)]
+    enum Effect {
+        #[default]
+        None,
+        Burn {
+            damage: Damage,
+            turns: u8,
+        },
+        Slow(Damage, u8),
+    }
+
+    #[derive(Debug, Default, Reflect, Component)]
+    #[reflect(Component)]
+    struct Enchanted {
+        power: Power,
+        effect: Effect,
+        runes: bevy::utils::HashMap<String, Damage>,
+    }
+
+    #[test]
+    fn inherit_merges_containers() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Enchanted>();
+        registry.register_type::<Damage>();
+        registry.register_type::<Power>();
+        registry.register_type::<Effect>();
+        registry.register_type::<bevy::utils::HashMap<String, Damage>>();
+        registry.clear_sources();
+        registry.add_source(crate::MemoryPrefabSource::from_iter([(
+            "staff.prefab",
+            "Staff { Enchanted {
+                power: Power(Damage { amount: 1, kind: \"fire\" }, 2),
+                effect: Effect::Burn { damage: Damage { amount: 3, kind: \"fire\" }, turns: 4 },
+                runes: HashMap<String,Damage> {
+                    \"a\": Damage { amount: 5, kind: \"ice\" },
+                    \"b\": Damage { amount: 6, kind: \"ice\" },
+                },
+            } }",
+        )]));
+
+        let enchanted = |input| {
+            let prefab = parse_prefab_file("derived.prefab", input, &registry).unwrap();
+            let mut enchanted = Enchanted::default();
+            match &prefab.steps[0] {
+                PrefabBuildStep::AddComponent(comp) => enchanted.apply(&*comp.reflect),
+                _ => unreachable!(),
+            }
+            enchanted
+        };
+
+        // Tuple structs, maps and enums of the same variant are merged field by field.
+        let merged = enchanted(
+            ": \"staff.prefab\" { Enchanted {
+                power: Power(Damage { amount: 10 }, 20),
+                effect: Effect::Burn { damage: Damage { amount: 30 }, turns: 40 },
+                runes: HashMap<String,Damage> { \"a\": Damage { amount: 50 } },
+            } }",
+        );
+        assert_eq!(merged.power.0.amount, 10);
+        assert_eq!(merged.power.0.kind, "fire");
+        assert_eq!(merged.power.1, 20);
+        match merged.effect {
+            Effect::Burn { damage, turns } => {
+                assert_eq!(
+                    (damage.amount, damage.kind.as_str(), turns),
+                    (30, "fire", 40)
+                );
+            }
+            e => panic!("unexpected effect {:?}", e),
+        }
+        assert_eq!(merged.runes["a"].amount, 50);
+        assert_eq!(merged.runes["a"].kind, "ice");
+        assert_eq!(merged.runes["b"].amount, 6);
+
+        // A different variant replaces the base variant.
+        let replaced = enchanted(
+            ": \"staff.prefab\" { Enchanted { effect: Effect::Slow(Damage { amount: 7, kind: \"frost\" }, 8) } }",
+        );
+        match replaced.effect {
+            Effect::Slow(damage, slow) => {
+                assert_eq!((damage.amount, damage.kind.as_str(), slow), (7, "frost", 8));
+            }
+            e => panic!("unexpected effect {:?}", e),
+        }
+        assert_eq!(replaced.power.1, 2);
+    }
+
+    #[test]
+    fn component_groups() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Transform>();
+        registry.register_type::<Visibility>();
+        registry.clear_sources();
+        registry.add_source(crate::MemoryPrefabSource::from_iter([
+            (
+                "common.prefab",
+                "group Visible { Visibility, Transform { translation: Vec3 { x: 1.0 } } }",
+            ),
+            ("loop.prefab", "group A { ..B } group B { ..A }"),
+        ]));
+
+        let input = "import \"common.prefab\"
+            group Scaled { ..Visible, Transform { scale: Vec3 { x: 2.0 } } }
+            Thing { ..Scaled, Visibility { is_visible: false } }";
+        let prefab = parse_prefab_file("thing.prefab", input, &registry).unwrap();
+        assert_eq!(prefab.imports, ["common.prefab"]);
+        let names: Vec<_> = prefab
+            .steps
+            .iter()
+            .map(|step| match step {
+                PrefabBuildStep::AddComponent(comp) => comp.type_name.as_str(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(names, ["Visibility", "Transform"]);
+
+        let parse = |input| parse_prefab_file("test.prefab", input, &registry);
+        assert!(matches!(
+            parse("{ ..Visible }").unwrap_err().kind(),
+            LoadPrefabError::UnknownGroup(_)
+        ));
+        assert!(matches!(
+            parse("import \"loop.prefab\" { ..A }").unwrap_err().kind(),
+            LoadPrefabError::GroupCycle(_)
+        ));
+        assert!(matches!(
+            parse("group A { Visibility } group A { Visibility } { ..A }")
+                .unwrap_err()
+                .kind(),
+            LoadPrefabError::DuplicateGroupName(_)
+        ));
+        assert!(matches!(
+            parse("group A { Visibility }").unwrap_err().kind(),
+            LoadPrefabError::MissingPrefab(_)
+        ));
+    }
+
+    #[test]
+    fn child_prefabs() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Transform>();
+        registry.register_type::<Visibility>();
+        registry.register_build_command::<DoStuff>();
+
+        let input = "Tank {
+            Transform,
+            children: [
+                Turret {
+                    Transform { translation: Vec3 { y: 1.0 } },
+                    children: [{ Visibility }],
+                },
+                { DOSTUFF!() },
+            ],
+        }";
+        let prefab = parse_prefab_file("tank.prefab", input, &registry).unwrap();
+        assert_eq!(prefab.steps.len(), 3);
+        let turret = match &prefab.steps[1] {
+            PrefabBuildStep::SpawnChild(child) => child,
+            _ => unreachable!(),
+        };
+        assert_eq!(turret.name(), Some("Turret"));
+        assert_eq!(turret.steps.len(), 2);
+        assert!(matches!(prefab.steps[2], PrefabBuildStep::SpawnChild(_)));
+    }
+
+    #[derive(Debug, Default, Reflect, Component)]
+    #[reflect(Component)]
+    struct Aim {
+        target: Option<Entity>,
+        owners: Vec<Entity>,
+    }
+
+    #[test]
+    fn entity_references() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Aim>();
+        registry.register_type::<Transform>();
+        registry.register_build_command::<DoStuff>();
+
+        let input = "Tank #tank {
+            Aim { target: Some(@muzzle), owners: [@tank] },
+            children: [
+                Turret {
+                    Aim { owners: [@tank, @muzzle] },
+                    children: [#muzzle { Transform }],
+                },
+            ],
+        }";
+        let prefab = parse_prefab_file("tank.prefab", input, &registry).unwrap();
+        assert_eq!(prefab.entity_name.as_deref(), Some("tank"));
+        match &prefab.steps[0] {
+            PrefabBuildStep::AddComponent(comp) => assert_eq!(comp.entity_refs, ["muzzle", "tank"]),
+            _ => unreachable!(),
+        }
+
+        let parse = |input| parse_prefab_file("test.prefab", input, &registry);
+        assert!(matches!(
+            parse("{ Aim { target: Some(@gun) } }").unwrap_err().kind(),
+            LoadPrefabError::UnknownEntityName(_)
+        ));
+        assert!(matches!(
+            parse("#a { Transform, children: [#a { Transform }] }")
+                .unwrap_err()
+                .kind(),
+            LoadPrefabError::DuplicateEntityName(_)
+        ));
+        assert!(matches!(
+            parse("#a { DOSTUFF!(target: @a) }").unwrap_err().kind(),
+            LoadPrefabError::MisplacedEntityReference(_)
+        ));
+        assert!(matches!(
+            parse("#a { Transform { scale: @a } }").unwrap_err().kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+    }
+
+    #[test]
+    fn prefab_params() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Weapon>();
+        registry.register_type::<Damage>();
+        registry.register_type::<Transform>();
+
+        let input = "{
+            params { amount: i32 = 10, kind: String = \"slash\" },
+            Weapon { name: \"Sword\", damage: Damage { amount: $amount, kind: $kind } },
+            children: [{ Weapon { damage: Damage { amount: $amount } } }],
+        }";
+        let prefab = parse_prefab_file("sword.prefab", input, &registry).unwrap();
+        assert_eq!(prefab.params.len(), 2);
+        assert_eq!(prefab.params[0].name, "amount");
+        assert_eq!(*prefab.params[0].default.cast_ref::<i32>(), 10);
+        match &prefab.steps[0] {
+            PrefabBuildStep::AddComponent(comp) => assert!(comp.uses_params),
+            _ => unreachable!(),
+        }
+
+        let parse = |input| parse_prefab_file("test.prefab", input, &registry);
+        assert!(matches!(
+            parse("{ Weapon { name: $name } }").unwrap_err().kind(),
+            LoadPrefabError::UnknownParam(_)
+        ));
+        assert!(matches!(
+            parse("{ params { amount: i32 = 1 }, Weapon { name: $amount } }")
+                .unwrap_err()
+                .kind(),
+            LoadPrefabError::MismatchedValueType(_, _, _)
+        ));
+        assert!(matches!(
+            parse("{ params { a: Missing = 1 }, Transform }")
+                .unwrap_err()
+                .kind(),
+            LoadPrefabError::UnknownParamType(_)
+        ));
+        assert!(parse("{ params { v: Vec3 = Vec3 { x: 2.0 } }, Transform { scale: $v } }").is_ok());
+        assert!(matches!(
+            parse("{ params { x: f32 = 1.0 }, Transform { translation: Vec3 { x: $x } } }")
+                .unwrap_err()
+                .kind(),
+            LoadPrefabError::MisplacedParam(_)
+        ));
+    }
 }
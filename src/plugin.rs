@@ -3,7 +3,13 @@ use bevy::{
     render::camera::{Camera, OrthographicProjection},
 };
 
-use crate::{build_commands::*, PrefabRegistry};
+use crate::{
+    bevy_commands::apply_pending_prefabs,
+    build_commands::*,
+    loader::PrefabAssetLoader,
+    reload::{reapply_reloaded_prefabs, watch_prefab_assets, watch_prefab_files},
+    Prefab, PrefabRegistry, PrefabReloaded,
+};
 
 /// Default plugin, registers many built-in bevy types and bundles and includes
 /// prefab commands for common assets.
@@ -21,7 +27,22 @@ impl Plugin for LazyPrefabsPlugin {
 pub struct LazyPrefabsMinimalPlugin;
 impl Plugin for LazyPrefabsMinimalPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<PrefabRegistry>();
+        app.init_resource::<PrefabRegistry>()
+            .add_event::<PrefabReloaded>()
+            .add_system_to_stage(CoreStage::First, watch_prefab_files)
+            .add_system_to_stage(CoreStage::PreUpdate, reapply_reloaded_prefabs);
+
+        // Prefabs can only be loaded as assets if the `AssetPlugin` was added first.
+        if app.world.contains_resource::<AssetServer>() {
+            let loader = PrefabAssetLoader::default();
+            app.world
+                .resource_mut::<PrefabRegistry>()
+                .share_types(loader.registry());
+            app.add_asset::<Prefab>()
+                .add_asset_loader(loader)
+                .add_system_to_stage(CoreStage::PreUpdate, apply_pending_prefabs)
+                .add_system_to_stage(CoreStage::First, watch_prefab_assets);
+        }
     }
 }
 
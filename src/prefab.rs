@@ -3,9 +3,15 @@ use std::sync::Arc;
 use bevy::{
     prelude::*,
     reflect::{DynamicStruct, TypeUuid},
+    utils::get_short_name,
 };
 use derivative::*;
 
+use crate::{
+    writer::{write_prefab, WritePrefabError},
+    LoadPrefabError, PrefabRegistry,
+};
+
 /// An asset built from a *.prefab* file.
 ///
 /// Prefabs can be retrieved from the [crate::PrefabRegistry] and applied to entities
@@ -22,24 +28,273 @@ use derivative::*;
 ///     commands.spawn_empty().insert_prefab(prefab);
 /// }
 /// ```
-#[derive(Debug, TypeUuid)]
+#[derive(Debug, Clone, TypeUuid)]
 #[uuid = "6ea14da5-6bf8-3ea1-9886-1d7bf6c17d2f"]
 pub struct Prefab {
-    #[allow(dead_code)]
     pub(crate) name: Option<String>,
+    /// The path the prefab was loaded from, if it was loaded from a file.
+    pub(crate) path: Option<String>,
+    /// The prefab this one inherits from. It's steps are already merged into this prefab's.
+    pub(crate) base: Option<String>,
+    /// The name other entities in the prefab can refer to this entity by, ie: `#muzzle`.
+    pub(crate) entity_name: Option<String>,
+    /// The parameters declared by the prefab, or the prefab it inherits from.
+    pub(crate) params: Vec<Arc<PrefabParam>>,
+    /// The files the prefab's file imports component groups from, directly or indirectly.
+    pub(crate) imports: Vec<String>,
     pub(crate) steps: Vec<PrefabBuildStep>,
 }
 
-#[derive(Debug)]
+impl Prefab {
+    /// Write the prefab as *.prefab* text. Parsing the text gives an equivalent prefab.
+    ///
+    /// Inheritance and component groups are already resolved, so the text includes every
+    /// component and build command of the base prefab and spliced groups.
+    ///
+    /// Returns an error if the prefab contains a value that can't be written in a prefab,
+    /// such as an entity that isn't part of the prefab or a float that isn't finite.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bevy::prelude::*;
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// let mut registry = PrefabRegistry::default();
+    /// registry.register_type::<Visibility>();
+    ///
+    /// let prefab = registry.load_from_str("a.prefab", "Ghost { Visibility { is_visible: false } }").unwrap();
+    /// assert_eq!(
+    ///     prefab.to_prefab_string().unwrap(),
+    ///     "Ghost {\n    Visibility { is_visible: false },\n}\n"
+    /// );
+    /// ```
+    pub fn to_prefab_string(&self) -> Result<String, WritePrefabError> {
+        write_prefab(self)
+    }
+
+    /// The prefab's name, if it was given one, ie: `Orc` for `Orc { ... }`.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The names of the prefabs this prefab or it's children inherit from or load with
+    /// `LoadPrefab` commands.
+    pub(crate) fn dependencies(&self) -> Vec<String> {
+        let mut dependencies: Vec<String> = self.base.iter().cloned().collect();
+        for step in self.steps.iter() {
+            match step {
+                PrefabBuildStep::RunCommand(command) if command.name == "LoadPrefab" => {
+                    // Names given by a parameter aren't known until the prefab is spawned.
+                    let name = command
+                        .properties
+                        .as_ref()
+                        .and_then(|props| props.field("name"))
+                        .and_then(|name| name.downcast_ref::<String>());
+                    dependencies.extend(name.cloned());
+                }
+                PrefabBuildStep::SpawnChild(child) => dependencies.extend(child.dependencies()),
+                _ => {}
+            }
+        }
+        dependencies
+    }
+
+    /// The entity names used by this prefab and it's children.
+    pub(crate) fn entity_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.entity_name.iter().map(String::as_str).collect();
+        for step in self.steps.iter() {
+            if let PrefabBuildStep::SpawnChild(child) = step {
+                names.extend(child.entity_names());
+            }
+        }
+        names
+    }
+
+    /// Whether this prefab or any of it's children inherit from the given prefab, or the
+    /// prefab's file imports the given file. Either way the prefab has to be parsed again
+    /// when the given prefab changes.
+    pub(crate) fn inherits_from(&self, name: &str) -> bool {
+        self.base.as_deref() == Some(name)
+            || self.imports.iter().any(|import| import == name)
+            || self.steps.iter().any(|step| match step {
+                PrefabBuildStep::SpawnChild(child) => child.inherits_from(name),
+                _ => false,
+            })
+    }
+}
+
+/// Builds a [Prefab] in code.
+///
+/// ## Example
+///
+/// ```
+/// use bevy::{prelude::*, reflect::DynamicStruct};
+/// use bevy_lazy_prefabs::*;
+///
+/// let mut registry = PrefabRegistry::default();
+/// registry.register_type::<Transform>();
+/// registry.register_build_command::<build_commands::InsertSpriteBundle>();
+///
+/// let mut properties = DynamicStruct::default();
+/// properties.insert("texture_path", "alien.png".to_string());
+///
+/// let prefab = PrefabBuilder::new()
+///     .with_command_properties("InsertSpriteBundle", properties)
+///     .with_component(Transform::from_xyz(1.0, 2.0, 0.0))
+///     .build(&registry)
+///     .unwrap();
+/// registry.add_prefab("alien.prefab", prefab).unwrap();
+/// ```
+#[derive(Default)]
+pub struct PrefabBuilder {
+    name: Option<String>,
+    steps: Vec<BuilderStep>,
+}
+
+enum BuilderStep {
+    AddComponent(Box<dyn Reflect>),
+    RunCommand(String, Option<DynamicStruct>),
+    SpawnChild(Prefab),
+}
+
+impl PrefabBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the prefab's name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Add a component. The component's type must be registered with the [PrefabRegistry].
+    ///
+    /// Components are applied in the order they're added, along with build commands.
+    pub fn with_component(self, component: impl Reflect) -> Self {
+        self.with_boxed_component(Box::new(component))
+    }
+
+    /// Add a component from a boxed value, ie: a `DynamicStruct` whose name is set to the
+    /// component's type name.
+    pub fn with_boxed_component(mut self, component: Box<dyn Reflect>) -> Self {
+        self.steps.push(BuilderStep::AddComponent(component));
+        self
+    }
+
+    /// Add a build command without any properties.
+    pub fn with_command(mut self, name: impl Into<String>) -> Self {
+        self.steps.push(BuilderStep::RunCommand(name.into(), None));
+        self
+    }
+
+    /// Add a build command with properties, as if they were written in a *.prefab* file.
+    pub fn with_command_properties(
+        mut self,
+        name: impl Into<String>,
+        properties: DynamicStruct,
+    ) -> Self {
+        self.steps
+            .push(BuilderStep::RunCommand(name.into(), Some(properties)));
+        self
+    }
+
+    /// Add a child entity, built from another prefab.
+    pub fn with_child(mut self, child: Prefab) -> Self {
+        self.steps.push(BuilderStep::SpawnChild(child));
+        self
+    }
+
+    /// Build the prefab, checking the components and build commands against the
+    /// registry the same way as prefabs loaded from a file.
+    pub fn build(self, registry: &PrefabRegistry) -> Result<Prefab, LoadPrefabError> {
+        let mut steps = Vec::new();
+
+        for step in self.steps {
+            match step {
+                BuilderStep::AddComponent(reflect) => {
+                    let type_data = registry
+                        .get_type_data_by_id(reflect.type_id())
+                        .or_else(|| registry.get_type_data(&get_short_name(reflect.type_name())));
+                    let type_data = type_data.ok_or_else(|| {
+                        LoadPrefabError::UnregisteredPrefabComponent(get_short_name(
+                            reflect.type_name(),
+                        ))
+                    })?;
+                    if type_data.registration.data::<ReflectComponent>().is_none() {
+                        return Err(LoadPrefabError::MissingReflectComponent(
+                            type_data.type_name.clone(),
+                        ));
+                    }
+                    steps.push(PrefabBuildStep::AddComponent(Arc::new(PrefabComponent {
+                        type_name: type_data.type_name.clone(),
+                        reflect,
+                        entity_refs: Vec::new(),
+                        uses_params: false,
+                    })));
+                }
+                BuilderStep::RunCommand(name, properties) => {
+                    if registry.get_build_command(&name).is_none() {
+                        return Err(LoadPrefabError::UnregisteredBuildCommand(name));
+                    }
+                    steps.push(PrefabBuildStep::RunCommand(Arc::new(PrefabCommandData {
+                        name,
+                        properties,
+                        uses_params: false,
+                    })));
+                }
+                BuilderStep::SpawnChild(child) => {
+                    steps.push(PrefabBuildStep::SpawnChild(Arc::new(child)));
+                }
+            }
+        }
+
+        Ok(Prefab {
+            name: self.name,
+            path: None,
+            base: None,
+            entity_name: None,
+            params: Vec::new(),
+            imports: Vec::new(),
+            steps,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) enum PrefabBuildStep {
     AddComponent(Arc<PrefabComponent>),
     RunCommand(Arc<PrefabCommandData>),
+    /// Spawn a child entity and apply a prefab to it.
+    SpawnChild(Arc<Prefab>),
 }
 
 #[derive(Debug)]
 pub(crate) struct PrefabComponent {
     pub type_name: String,
     pub reflect: Box<dyn Reflect>,
+    /// The names of the entities referred to by the component. References are stored in
+    /// the component as placeholder entities, see [PrefabComponent::entity_placeholder].
+    pub entity_refs: Vec<String>,
+    /// Whether the component contains any [ParamRef]s.
+    pub uses_params: bool,
+}
+
+impl PrefabComponent {
+    /// A placeholder for the entity named by `entity_refs[index]`, replaced with the real
+    /// entity when the component is added.
+    pub fn entity_placeholder(index: usize) -> Entity {
+        Entity::from_bits((u32::MAX as u64) << 32 | index as u64)
+    }
+
+    /// The index into `entity_refs` of a placeholder entity.
+    pub fn placeholder_index(entity: Entity) -> Option<usize> {
+        match entity.generation() {
+            u32::MAX => Some(entity.index() as usize),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Derivative)]
@@ -48,4 +303,72 @@ pub(crate) struct PrefabCommandData {
     pub name: String,
     #[derivative(Debug = "ignore")]
     pub properties: Option<DynamicStruct>,
+    /// Whether the properties contain any [ParamRef]s.
+    pub uses_params: bool,
+}
+
+/// A parameter declared in a prefab's `params` block.
+#[derive(Debug)]
+pub(crate) struct PrefabParam {
+    pub name: String,
+    /// The value used when the parameter isn't given when the prefab is inserted. Arguments
+    /// must be the same type.
+    pub default: Box<dyn Reflect>,
+}
+
+/// A placeholder for a parameter in a prefab value, ie: `$hp`. Replaced with the parameter's
+/// value when the prefab is inserted.
+#[derive(Reflect, Clone, Debug, Default, PartialEq)]
+#[reflect_value(PartialEq)]
+pub(crate) struct ParamRef(pub String);
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use crate::{bevy_commands::apply_prefab, LoadPrefabError, PrefabBuilder, PrefabRegistry};
+
+    #[test]
+    fn builder_build() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Transform>();
+        registry.register_type::<Vec3>();
+
+        let prefab = PrefabBuilder::new()
+            .with_component(Transform::from_xyz(1.0, 2.0, 3.0))
+            .build(&registry)
+            .unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(registry);
+        let entity = world.spawn_empty().id();
+        apply_prefab(&mut world, entity, &prefab, None);
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().translation,
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+
+        let registry = world.resource::<PrefabRegistry>();
+        assert!(matches!(
+            PrefabBuilder::new()
+                .with_component(Visibility::default())
+                .build(registry)
+                .unwrap_err(),
+            LoadPrefabError::UnregisteredPrefabComponent(_)
+        ));
+        assert!(matches!(
+            PrefabBuilder::new()
+                .with_component(Vec3::ONE)
+                .build(registry)
+                .unwrap_err(),
+            LoadPrefabError::MissingReflectComponent(_)
+        ));
+        assert!(matches!(
+            PrefabBuilder::new()
+                .with_command("Missing")
+                .build(registry)
+                .unwrap_err(),
+            LoadPrefabError::UnregisteredBuildCommand(_)
+        ));
+    }
 }
@@ -1,26 +1,52 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    any::TypeId,
+    borrow::Cow,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
 
 use bevy::{
-    asset::{AssetIo, FileAssetIo},
     prelude::*,
     reflect::{GetTypeRegistration, ReflectRef, TypeRegistration},
     utils::HashMap,
 };
-use futures_lite::future;
 
 use crate::{
-    build_commands::BuildPrefabCommand, parse::parse_prefab_string, parse::LoadPrefabError,
+    build_commands::BuildPrefabCommand,
+    diagnostic::PrefabDiagnostic,
+    manifest::{manifest_type, ManifestCommand, ManifestProperty, ManifestType, TypeManifest},
+    parse::LoadPrefabError,
+    parse::{diagnose_prefab_string, parse_prefab_file, split_prefab_name},
     prefab::Prefab,
+    snapshot::snapshot_entity,
+    source::{PrefabSource, PrefabSources, SourceId},
 };
 
 /// Manages and caches [Prefab] related data.
 #[derive(Default, Resource)]
 pub struct PrefabRegistry {
     type_data: HashMap<String, TypeInfo>,
+    type_ids: HashMap<TypeId, String>,
     commands: HashMap<String, Arc<dyn BuildPrefabCommand + Send + Sync + 'static>>,
+    /// Types and build commands described by a [TypeManifest] instead of registered.
+    described_types: HashMap<String, Arc<ManifestType>>,
+    described_commands: HashMap<String, ManifestCommand>,
     prefabs: HashMap<String, Arc<Prefab>>,
+    /// The prefabs each cached prefab inherits from or loads with `LoadPrefab`.
+    dependencies: HashMap<String, Vec<String>>,
+    sources: PrefabSources,
+    /// The source each cached prefab was read from and when it was last modified.
+    modified: HashMap<String, (SourceId, SystemTime)>,
+    watch_for_changes: bool,
+    last_watch: Option<Instant>,
+    /// The registry the asset loader parses prefabs with, kept up to date with the types and
+    /// build commands registered here.
+    loader: Option<Arc<RwLock<PrefabRegistry>>>,
 }
 
+/// How often prefab files are checked for changes.
+pub(crate) const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
 impl PrefabRegistry {
     /// Register a component for use in a [Prefab].
     ///
@@ -55,10 +81,15 @@ impl PrefabRegistry {
         let info = TypeInfo {
             type_name: name.clone(),
             reflect_type: instance.reflect_ref().into(),
+            default_value: instance.clone_value(),
             registration: reg,
         };
 
-        self.type_data.insert(name, info);
+        let key = type_key(&name).into_owned();
+        self.type_ids
+            .insert(info.registration.type_id(), key.clone());
+        self.type_data.insert(key, info);
+        self.sync_loader();
     }
 
     /// Register a [BuildPrefabCommand] for use in a [Prefab].
@@ -70,42 +101,565 @@ impl PrefabRegistry {
     ) {
         let t = T::default();
         self.commands.insert(t.key().to_string(), Arc::new(t));
+        self.sync_loader();
+    }
+
+    /// Add the types and build commands described by a [TypeManifest], so prefabs that use
+    /// them can be checked with [PrefabRegistry::diagnose] without the game's types, ie: by
+    /// the `prefab-check` binary.
+    ///
+    /// Described types are checked the same way as registered types, including the types of
+    /// their field values. Prefabs that use them can be loaded, but their components can't be
+    /// added to entities. Registered types and commands take priority over described ones.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// let manifest = TypeManifest::from_json(r#"{
+    ///     "types": [
+    ///         {
+    ///             "name": "Health",
+    ///             "component": true,
+    ///             "kind": "struct",
+    ///             "fields": [{ "name": "max", "type": "i32" }]
+    ///         }
+    ///     ]
+    /// }"#).unwrap();
+    ///
+    /// let mut registry = PrefabRegistry::default();
+    /// registry.register_manifest(&manifest);
+    /// registry.clear_sources();
+    /// registry.add_source(MemoryPrefabSource::from_iter([(
+    ///     "orc.prefab",
+    ///     "Orc { Health { maxx: 10 } }",
+    /// )]));
+    /// assert_eq!(registry.diagnose("orc.prefab").len(), 1);
+    /// ```
+    pub fn register_manifest(&mut self, manifest: &TypeManifest) {
+        for t in manifest.types.iter() {
+            let key = type_key(&t.name).into_owned();
+            self.described_types.insert(key, Arc::new(t.clone()));
+        }
+        for command in manifest.commands.iter() {
+            let name = command.name.clone();
+            self.described_commands.insert(name, command.clone());
+        }
+        self.sync_loader();
     }
 
     /// Load the [Prefab] from disk, or retrieve it if it's already been loaded.
     ///
-    /// When first called for a prefab this will load it from disk and cache it internally.
-    /// Future load calls for the same prefab will re-use this cached result.
+    /// When first called for a prefab this will read it from the registry's sources, by default
+    /// the *assets/* folder, and cache it internally. Future load calls for the same prefab will
+    /// re-use this cached result. See [PrefabRegistry::add_source].
+    ///
+    /// Named prefabs in a file with several prefabs are loaded as `file.prefab#Name`,
+    /// otherwise the first prefab in the file is loaded.
+    ///
+    /// Errors in the prefab file are returned as [LoadPrefabError::Located], which includes
+    /// the file name, line and column of the error. Components and build commands used by the
+    /// prefab must already be registered, and components must have the `#[reflect(Component)]`
+    /// attribute.
     pub fn load(&mut self, name: &str) -> Result<&Arc<Prefab>, LoadPrefabError> {
-        if self.prefabs.contains_key(name) {
-            return Ok(self.prefabs.get(name).unwrap());
+        self.load_dependency(name, &mut Vec::new())?;
+        Ok(self.prefabs.get(name).unwrap())
+    }
+
+    /// Load a prefab along with every prefab it depends on. `chain` is the
+    /// list of prefabs that led to this one being loaded, used to detect cycles.
+    fn load_dependency(
+        &mut self,
+        name: &str,
+        chain: &mut Vec<String>,
+    ) -> Result<Arc<Prefab>, LoadPrefabError> {
+        if let Some(start) = chain.iter().position(|n| n == name) {
+            let mut cycle = chain[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(LoadPrefabError::Cycle(cycle));
+        }
+
+        // Cached prefabs already had their dependencies loaded when they were cached.
+        if let Some(prefab) = self.prefabs.get(name) {
+            return Ok(prefab.clone());
+        }
+
+        let prefab = Arc::new(self.read_prefab(name)?);
+        self.load_dependencies(name, &prefab, chain)?;
+        self.cache_prefab(name, prefab.clone());
+        if let Err(e) = self.check_cycle(name) {
+            self.uncache_prefab(name);
+            return Err(e);
+        }
+        Ok(prefab)
+    }
+
+    fn load_dependencies(
+        &mut self,
+        name: &str,
+        prefab: &Prefab,
+        chain: &mut Vec<String>,
+    ) -> Result<(), LoadPrefabError> {
+        chain.push(name.to_string());
+        for dependency in prefab.dependencies() {
+            self.load_dependency(&dependency, chain)?;
+        }
+        chain.pop();
+        Ok(())
+    }
+
+    /// Ensure a cached prefab doesn't depend on itself through the prefabs that were
+    /// cached before it.
+    fn check_cycle(&self, name: &str) -> Result<(), LoadPrefabError> {
+        fn visit(registry: &PrefabRegistry, name: &str, chain: &mut Vec<String>) -> bool {
+            for dependency in registry.dependencies_of(chain.last().unwrap()) {
+                if dependency == name {
+                    chain.push(dependency.clone());
+                    return true;
+                }
+                if chain.contains(dependency) {
+                    continue;
+                }
+                chain.push(dependency.clone());
+                if visit(registry, name, chain) {
+                    return true;
+                }
+                chain.pop();
+            }
+            false
+        }
+
+        let mut chain = vec![name.to_string()];
+        match visit(self, name, &mut chain) {
+            true => Err(LoadPrefabError::Cycle(chain)),
+            false => Ok(()),
+        }
+    }
+
+    /// Replace a cached prefab, keeping the previous version if the new one's
+    /// dependencies can't be loaded.
+    fn replace_prefab(&mut self, name: &str, prefab: Prefab) -> Result<(), LoadPrefabError> {
+        let prefab = Arc::new(prefab);
+        let previous = self.cache_prefab(name, prefab.clone());
+        let result = self
+            .load_dependencies(name, &prefab, &mut Vec::new())
+            .and_then(|_| self.check_cycle(name));
+        if let Err(e) = result {
+            match previous {
+                Some(previous) => {
+                    self.cache_prefab(name, previous);
+                }
+                None => self.uncache_prefab(name),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Add a prefab to the cache and record its dependencies, returning the prefab it replaced.
+    fn cache_prefab(&mut self, name: &str, prefab: Arc<Prefab>) -> Option<Arc<Prefab>> {
+        self.dependencies
+            .insert(name.to_string(), prefab.dependencies());
+        self.prefabs.insert(name.to_string(), prefab)
+    }
+
+    fn uncache_prefab(&mut self, name: &str) {
+        self.prefabs.remove(name);
+        self.dependencies.remove(name);
+        self.modified.remove(name);
+    }
+
+    /// Parse a [Prefab] from text and cache it under the given name, replacing any prefab
+    /// already loaded with that name.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bevy::prelude::*;
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// let mut registry = PrefabRegistry::default();
+    /// registry.register_type::<Transform>();
+    ///
+    /// let text = "{ Transform { translation: Vec3 { x: 5.0 } } }";
+    /// registry.load_from_str("moved.prefab", text).unwrap();
+    /// assert!(registry.load("moved.prefab").is_ok());
+    /// ```
+    pub fn load_from_str(
+        &mut self,
+        name: &str,
+        text: &str,
+    ) -> Result<&Arc<Prefab>, LoadPrefabError> {
+        let prefab = parse_prefab_file(name, text, self)?;
+        self.add_prefab(name, prefab)
+    }
+
+    /// Cache a [Prefab] under the given name, replacing any prefab already loaded with
+    /// that name. Future load calls for the name will return this prefab.
+    ///
+    /// Prefabs it loads with `LoadPrefab` are loaded too. If they fail to load, or they
+    /// load this prefab back, the prefab isn't added.
+    ///
+    /// Prefabs can be built in code with a [PrefabBuilder](crate::PrefabBuilder).
+    pub fn add_prefab(
+        &mut self,
+        name: &str,
+        mut prefab: Prefab,
+    ) -> Result<&Arc<Prefab>, LoadPrefabError> {
+        prefab.path = Some(name.to_string());
+        self.replace_prefab(name, prefab)?;
+        // The prefab no longer matches any file it was read from.
+        self.modified.remove(name);
+        Ok(self.prefabs.get(name).unwrap())
+    }
+
+    fn read_prefab(&mut self, name: &str) -> Result<Prefab, LoadPrefabError> {
+        let (prefab_string, source) = self.read_prefab_string(name)?;
+        let (file, _) = split_prefab_name(name);
+        match self.sources.modified(source, file) {
+            Some(modified) => self.modified.insert(name.to_string(), (source, modified)),
+            None => self.modified.remove(name),
+        };
+
+        let prefab = parse_prefab_file(name, &prefab_string, self)?;
+        for import in prefab.imports.iter() {
+            self.watch_import(import);
+        }
+        Ok(prefab)
+    }
+
+    /// Record when a file imported by a prefab was last modified. Imported files aren't
+    /// cached, but prefabs that import them are reloaded when they change.
+    fn watch_import(&mut self, name: &str) {
+        let modified = self.sources.read(name).ok().and_then(|(_, source)| {
+            let modified = self.sources.modified(source, name)?;
+            Some((source, modified))
+        });
+        match modified {
+            Some(modified) => self.modified.insert(name.to_string(), modified),
+            None => self.modified.remove(name),
         };
+    }
 
-        let io = FileAssetIo::new("assets/", false);
-        let result = future::block_on(io.load_path(Path::new(name)));
+    /// Read the text of a prefab's file and the id of the source it was read from.
+    pub(crate) fn read_prefab_string(
+        &self,
+        name: &str,
+    ) -> Result<(String, SourceId), LoadPrefabError> {
+        let (file, _) = split_prefab_name(name);
+        self.sources
+            .read(file)
+            .map_err(|e| LoadPrefabError::FileReadError(file.to_string(), e))
+    }
 
-        if let Err(error) = result {
-            return Err(LoadPrefabError::FileReadError(error));
+    /// Add a [PrefabSource] to read prefab files from.
+    ///
+    /// Sources are searched starting from the most recently added, so later sources
+    /// override prefabs in earlier ones. By default prefabs are read from the *assets/*
+    /// folder. Prefabs that were already loaded aren't affected.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bevy::prelude::*;
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// let mut registry = PrefabRegistry::default();
+    /// registry.register_type::<Transform>();
+    ///
+    /// let mut embedded = MemoryPrefabSource::new();
+    /// embedded.insert("origin.prefab", "{ Transform }");
+    /// registry.add_source(embedded);
+    ///
+    /// assert!(registry.load("origin.prefab").is_ok());
+    /// ```
+    pub fn add_source(&mut self, source: impl PrefabSource + 'static) {
+        self.sources.push(Box::new(source));
+    }
+
+    /// Remove every [PrefabSource], including the default *assets/* folder.
+    ///
+    /// Prefabs that were already loaded stay cached, but they're no longer reloaded when
+    /// their files change.
+    pub fn clear_sources(&mut self) {
+        self.sources.clear();
+    }
+
+    /// Watch loaded prefab files for changes.
+    ///
+    /// When a prefab file changes it's reloaded, and the new version is applied to every
+    /// entity it was inserted into, as well as entities with prefabs that load it with
+    /// `LoadPrefab`. A [PrefabReloaded](crate::PrefabReloaded) event is sent for each
+    /// reloaded prefab.
+    ///
+    /// Components and build commands are applied the same way as when the prefab was first
+    /// inserted, so components that were removed from the file aren't removed from entities.
+    pub fn watch_for_changes(&mut self) {
+        self.watch_for_changes = true;
+    }
+
+    /// Check for changes on the next call to [PrefabRegistry::reload_changed], even if it's
+    /// sooner than [WATCH_INTERVAL].
+    #[cfg(test)]
+    pub(crate) fn force_check(&mut self) {
+        self.last_watch = None;
+    }
+
+    /// Reload cached prefabs whose files have changed, returning their names.
+    ///
+    /// Prefabs that fail to reload keep their previous version.
+    pub(crate) fn reload_changed(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let ready = match self.last_watch {
+            Some(last) => now.duration_since(last) >= WATCH_INTERVAL,
+            None => true,
+        };
+        if !self.watch_for_changes || !ready {
+            return Vec::new();
         }
+        self.last_watch = Some(now);
 
-        let data = result.unwrap();
-        let prefab_string = String::from_utf8_lossy(&data);
+        let mut changed: Vec<_> = self
+            .modified
+            .iter()
+            .filter(|(name, (source, modified))| {
+                let (file, _) = split_prefab_name(name);
+                matches!(self.sources.modified(*source, file), Some(time) if time > *modified)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
 
-        match parse_prefab_string(&prefab_string, self) {
-            Ok(prefab) => {
-                //let entry = self.prefab_map.entry(prefab_name.to_string());
-                let entry = self.prefabs.entry(name.to_string());
-                Ok(entry.or_insert_with(|| Arc::new(prefab)))
+        // Prefabs that inherit from a changed prefab or import a changed file are reloaded
+        // after it, since the base prefab's steps and imported groups are merged into them.
+        let mut i = 0;
+        while i < changed.len() {
+            let inheriting: Vec<_> = self
+                .prefabs
+                .iter()
+                .filter(|(name, prefab)| {
+                    prefab.inherits_from(&changed[i]) && !changed.contains(name)
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            changed.extend(inheriting);
+            i += 1;
+        }
+
+        let mut reloaded = Vec::new();
+        for name in changed {
+            if !self.prefabs.contains_key(&name) {
+                self.watch_import(&name);
+                continue;
+            }
+            match self
+                .read_prefab(&name)
+                .and_then(|prefab| self.replace_prefab(&name, prefab))
+            {
+                Ok(()) => reloaded.push(name),
+                Err(e) => error!("Error reloading prefab - {}", e),
             }
-            Err(e) => Err(e),
         }
+        reloaded
+    }
+
+    /// A cached [Prefab], if it's been loaded.
+    pub(crate) fn cached_prefab(&self, name: &str) -> Option<&Arc<Prefab>> {
+        self.prefabs.get(name)
+    }
+
+    /// Every cached [Prefab].
+    pub(crate) fn cached_prefabs(&self) -> impl Iterator<Item = &Arc<Prefab>> {
+        self.prefabs.values()
+    }
+
+    /// Check a prefab file for problems without loading it.
+    ///
+    /// Unlike [PrefabRegistry::load], parsing continues after errors in components, fields
+    /// and build commands so every problem in the file is reported at once. Syntax errors
+    /// still stop parsing.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// let registry = PrefabRegistry::default();
+    /// for diagnostic in registry.diagnose("sword.prefab") {
+    ///     eprintln!("{}", diagnostic);
+    /// }
+    /// ```
+    pub fn diagnose(&self, name: &str) -> Vec<PrefabDiagnostic> {
+        match self.read_prefab_string(name) {
+            Ok((prefab_string, _)) => diagnose_prefab_string(name, &prefab_string, self)
+                .into_iter()
+                .map(|d| PrefabDiagnostic {
+                    error: d.error.with_path(name),
+                    ..d
+                })
+                .collect(),
+            Err(e) => vec![PrefabDiagnostic::error(e)],
+        }
+    }
+
+    /// Build a [Prefab] from an entity's components and children, ie: to save changes made to
+    /// a level while the game is running.
+    ///
+    /// Only components registered with [PrefabRegistry::register_type] are included. When
+    /// `omit_defaults` is true, struct fields that are the same as the type's default value
+    /// are left out. Components referring to the entity or one of it's descendants are
+    /// written as named entity references, ie: `@entity5`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bevy::prelude::*;
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// let mut registry = PrefabRegistry::default();
+    /// registry.register_type::<Transform>();
+    ///
+    /// let mut world = World::new();
+    /// let entity = world.spawn(Transform::from_xyz(1.0, 0.0, 0.0)).id();
+    ///
+    /// let prefab = registry.entity_to_prefab(&world, entity, true);
+    /// assert_eq!(
+    ///     prefab.to_prefab_string().unwrap(),
+    ///     "{\n    Transform { translation: Vec3 { x: 1.0 } },\n}\n"
+    /// );
+    /// ```
+    pub fn entity_to_prefab(&self, world: &World, entity: Entity, omit_defaults: bool) -> Prefab {
+        snapshot_entity(self, world, entity, omit_defaults)
+    }
+
+    /// Describe every registered type and build command, so tools outside the game can
+    /// check prefabs without the game's types. See [TypeManifest].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bevy::prelude::*;
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// let mut registry = PrefabRegistry::default();
+    /// registry.register_type::<Visibility>();
+    ///
+    /// let json = registry.manifest().to_json();
+    /// let manifest = TypeManifest::from_json(&json).unwrap();
+    /// assert!(manifest.get_type("Visibility").unwrap().component);
+    /// ```
+    pub fn manifest(&self) -> TypeManifest {
+        let mut types: Vec<_> = self.type_data.values().map(manifest_type).collect();
+        types.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut commands: Vec<_> = self
+            .commands
+            .iter()
+            .map(|(name, command)| ManifestCommand {
+                name: name.clone(),
+                properties: command
+                    .properties()
+                    .iter()
+                    .map(|(name, type_name)| ManifestProperty {
+                        name: name.to_string(),
+                        type_name: type_name.to_string(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+
+        TypeManifest { types, commands }
     }
 
     /// Remove a cached [Prefab] from the registry.
     ///
-    /// The next time the prefab is loaded it will be read from disk.
+    /// The next time the prefab is loaded it will be read from disk. Prefabs that inherit
+    /// from it or load it with `LoadPrefab` stay cached, use [PrefabRegistry::invalidate]
+    /// to remove them too.
     pub fn unload_prefab(&mut self, name: &str) {
-        self.prefabs.remove(name);
+        self.uncache_prefab(name);
+    }
+
+    /// Remove a cached [Prefab] and every cached prefab that inherits from it or loads it
+    /// with `LoadPrefab`, directly or indirectly. Returns the names of the removed prefabs.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bevy::prelude::*;
+    /// use bevy_lazy_prefabs::*;
+    ///
+    /// let mut registry = PrefabRegistry::default();
+    /// registry.register_type::<Transform>();
+    /// registry.register_build_command::<build_commands::LoadPrefab>();
+    ///
+    /// registry.load_from_str("base.prefab", "{ Transform }").unwrap();
+    /// registry.load_from_str("unit.prefab", "{ LoadPrefab!(name: \"base.prefab\") }").unwrap();
+    /// assert_eq!(registry.dependents_of("base.prefab"), ["unit.prefab"]);
+    ///
+    /// let removed = registry.invalidate("base.prefab");
+    /// assert_eq!(removed, ["base.prefab", "unit.prefab"]);
+    /// ```
+    pub fn invalidate(&mut self, name: &str) -> Vec<String> {
+        let mut removed = Vec::new();
+        let mut remaining = vec![name.to_string()];
+        while let Some(name) = remaining.pop() {
+            if !self.prefabs.contains_key(&name) {
+                continue;
+            }
+            remaining.extend(self.dependents_of(&name).into_iter().map(String::from));
+            self.uncache_prefab(&name);
+            removed.push(name);
+        }
+        removed
+    }
+
+    /// The names of the cached prefabs that the given prefab inherits from or loads with
+    /// `LoadPrefab`.
+    pub fn dependencies_of(&self, name: &str) -> &[String] {
+        match self.dependencies.get(name) {
+            Some(dependencies) => dependencies,
+            None => &[],
+        }
+    }
+
+    /// The names of the cached prefabs that inherit from the given prefab or load it with
+    /// `LoadPrefab`.
+    pub fn dependents_of(&self, name: &str) -> Vec<&str> {
+        let mut dependents: Vec<_> = self
+            .dependencies
+            .iter()
+            .filter(|(_, dependencies)| dependencies.iter().any(|d| d == name))
+            .map(|(dependent, _)| dependent.as_str())
+            .collect();
+        dependents.sort_unstable();
+        dependents
+    }
+
+    /// Share the registered types and build commands with the asset loader's registry, now and
+    /// whenever another one is registered.
+    pub(crate) fn share_types(&mut self, loader: Arc<RwLock<PrefabRegistry>>) {
+        self.loader = Some(loader);
+        self.sync_loader();
+    }
+
+    fn sync_loader(&self) {
+        if let Some(loader) = &self.loader {
+            *loader.write().unwrap() = self.clone_types();
+        }
+    }
+
+    /// A copy of the registered types and build commands, without any cached prefabs.
+    pub(crate) fn clone_types(&self) -> PrefabRegistry {
+        PrefabRegistry {
+            type_data: self.type_data.clone(),
+            type_ids: self.type_ids.clone(),
+            commands: self.commands.clone(),
+            described_types: self.described_types.clone(),
+            described_commands: self.described_commands.clone(),
+            ..Default::default()
+        }
     }
 
     pub(crate) fn get_build_command(
@@ -115,18 +669,77 @@ impl PrefabRegistry {
         self.commands.get(name)
     }
 
+    /// The properties declared by a registered or described build command, or [None] if
+    /// there's no command with the name.
+    pub(crate) fn command_properties(&self, name: &str) -> Option<Vec<(&str, &str)>> {
+        if let Some(command) = self.commands.get(name) {
+            return Some(command.properties().to_vec());
+        }
+        let command = self.described_commands.get(name)?;
+        let properties = command.properties.iter();
+        Some(
+            properties
+                .map(|p| (p.name.as_str(), p.type_name.as_str()))
+                .collect(),
+        )
+    }
+
     pub(crate) fn get_type_data(&self, name: &str) -> Option<&TypeInfo> {
-        self.type_data.get(name)
+        self.type_data.get(type_key(name).as_ref())
+    }
+
+    /// A type described by a [TypeManifest] that isn't registered.
+    pub(crate) fn get_described_type(&self, name: &str) -> Option<&Arc<ManifestType>> {
+        let key = type_key(name);
+        match self.type_data.contains_key(key.as_ref()) {
+            true => None,
+            false => self.described_types.get(key.as_ref()),
+        }
+    }
+
+    /// Whether a registered or described type can be added to entities.
+    pub(crate) fn is_component(&self, name: &str) -> bool {
+        match self.get_type_data(name) {
+            Some(t) => t.registration.data::<ReflectComponent>().is_some(),
+            None => matches!(self.get_described_type(name), Some(t) if t.component),
+        }
+    }
+
+    pub(crate) fn get_type_data_by_id(&self, type_id: TypeId) -> Option<&TypeInfo> {
+        self.type_ids
+            .get(&type_id)
+            .and_then(|key| self.type_data.get(key))
+    }
+}
+
+/// Type names are stored without whitespace so generic types can be referred to
+/// as either `HashMap<String, i32>` or `HashMap<String,i32>`.
+fn type_key(name: &str) -> Cow<'_, str> {
+    if name.contains(char::is_whitespace) {
+        Cow::Owned(name.split_whitespace().collect())
+    } else {
+        Cow::Borrowed(name)
     }
 }
 
 pub(crate) struct TypeInfo {
-    #[allow(dead_code)]
     pub type_name: String,
     pub reflect_type: ReflectType,
+    pub default_value: Box<dyn Reflect>,
     pub registration: TypeRegistration,
 }
 
+impl Clone for TypeInfo {
+    fn clone(&self) -> Self {
+        TypeInfo {
+            type_name: self.type_name.clone(),
+            reflect_type: self.reflect_type.clone(),
+            default_value: self.default_value.clone_value(),
+            registration: self.registration.clone(),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub(crate) enum ReflectType {
     Struct,
@@ -153,3 +766,288 @@ impl From<ReflectRef<'_>> for ReflectType {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, RwLock};
+
+    use bevy::prelude::*;
+
+    use crate::{
+        build_commands::LoadPrefab, parse::parse_prefab_file_all, prefab::PrefabBuildStep,
+        source::test::TestSource, LoadPrefabError, MemoryPrefabSource, PrefabRegistry,
+    };
+
+    fn registry(prefabs: &[(&str, &str)]) -> PrefabRegistry {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Transform>();
+        registry.register_build_command::<LoadPrefab>();
+        registry.clear_sources();
+        registry.add_source(prefabs.iter().copied().collect::<MemoryPrefabSource>());
+        registry
+    }
+
+    #[test]
+    fn load_cycle() {
+        let mut registry = registry(&[
+            ("a.prefab", "{ LoadPrefab!(name: \"b.prefab\") }"),
+            ("b.prefab", "{ Transform, LoadPrefab!(name: \"c.prefab\") }"),
+            ("c.prefab", "{ LoadPrefab!(name: \"a.prefab\") }"),
+        ]);
+
+        match registry.load("a.prefab").unwrap_err() {
+            LoadPrefabError::Cycle(chain) => {
+                assert_eq!(chain, ["a.prefab", "b.prefab", "c.prefab", "a.prefab"])
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        assert_eq!(registry.cached_prefabs().count(), 0);
+
+        match registry.load("b.prefab").unwrap_err() {
+            LoadPrefabError::Cycle(chain) => {
+                assert_eq!(chain, ["b.prefab", "c.prefab", "a.prefab", "b.prefab"])
+            }
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn load_param_name() {
+        let mut registry = registry(&[
+            (
+                "a.prefab",
+                "{ params { n: String = \"b.prefab\" }, LoadPrefab!(name: $n) }",
+            ),
+            ("b.prefab", "{ Transform }"),
+        ]);
+
+        let prefab = registry.load("a.prefab").unwrap();
+        assert_eq!(prefab.steps.len(), 1);
+        // The prefab named by the parameter is loaded when the prefab is spawned.
+        assert_eq!(registry.cached_prefabs().count(), 1);
+    }
+
+    #[test]
+    fn replace_cycle() {
+        let mut registry = registry(&[
+            ("a.prefab", "{ LoadPrefab!(name: \"b.prefab\") }"),
+            ("b.prefab", "{ Transform }"),
+        ]);
+        registry.load("a.prefab").unwrap();
+        assert_eq!(registry.cached_prefabs().count(), 2);
+
+        let err = registry
+            .load_from_str("b.prefab", "{ LoadPrefab!(name: \"a.prefab\") }")
+            .unwrap_err();
+        assert!(matches!(err, LoadPrefabError::Cycle(_)));
+        // The previous version is kept.
+        assert_eq!(registry.load("b.prefab").unwrap().steps.len(), 1);
+        assert!(registry.load_from_str("b.prefab", "{ Transform }").is_ok());
+    }
+
+    #[test]
+    fn invalidate_dependents() {
+        let mut registry = registry(&[
+            ("a.prefab", "{ LoadPrefab!(name: \"b.prefab\") }"),
+            ("b.prefab", "{ LoadPrefab!(name: \"c.prefab\") }"),
+            ("c.prefab", "{ Transform }"),
+            ("d.prefab", "{ LoadPrefab!(name: \"c.prefab\") }"),
+            ("e.prefab", "{ Transform }"),
+        ]);
+        for name in ["a.prefab", "d.prefab", "e.prefab"] {
+            registry.load(name).unwrap();
+        }
+
+        assert_eq!(registry.dependencies_of("a.prefab"), ["b.prefab"]);
+        assert!(registry.dependencies_of("c.prefab").is_empty());
+        assert_eq!(registry.dependents_of("c.prefab"), ["b.prefab", "d.prefab"]);
+
+        let mut removed = registry.invalidate("c.prefab");
+        removed.sort();
+        assert_eq!(removed, ["a.prefab", "b.prefab", "c.prefab", "d.prefab"]);
+        assert_eq!(registry.cached_prefabs().count(), 1);
+        assert!(registry.dependents_of("c.prefab").is_empty());
+        assert!(registry.invalidate("c.prefab").is_empty());
+    }
+
+    #[test]
+    fn named_prefabs() {
+        let mut registry = registry(&[
+            (
+                "enemies.prefab",
+                "Goblin { Transform } Orc { Transform, LoadPrefab!(name: \"enemies.prefab#Goblin\") }",
+            ),
+            ("boss.prefab", ": \"enemies.prefab#Orc\" { Transform }"),
+        ]);
+
+        let orc = registry.load("enemies.prefab#Orc").unwrap();
+        assert_eq!(orc.name(), Some("Orc"));
+        assert_eq!(orc.steps.len(), 2);
+        assert_eq!(
+            registry.dependencies_of("enemies.prefab#Orc"),
+            ["enemies.prefab#Goblin"]
+        );
+        assert_eq!(
+            registry.load("enemies.prefab").unwrap().name(),
+            Some("Goblin")
+        );
+        assert_eq!(
+            registry.load("boss.prefab").unwrap().base.as_deref(),
+            Some("enemies.prefab#Orc")
+        );
+
+        assert!(matches!(
+            registry.load("enemies.prefab#Troll").unwrap_err(),
+            LoadPrefabError::UnknownPrefabName(_, _)
+        ));
+        let err = registry
+            .load_from_str("twins.prefab", "Twin { Transform } Twin { Transform }")
+            .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            LoadPrefabError::DuplicatePrefabName(_)
+        ));
+    }
+
+    #[test]
+    fn parse_all_named_prefabs() {
+        let input =
+            "{ Transform } Goblin { Transform } Orc : \"enemies.prefab#Goblin\" { Visibility }";
+        let mut registry = registry(&[("enemies.prefab", input)]);
+        registry.register_type::<Visibility>();
+
+        let (first, named) = parse_prefab_file_all("enemies.prefab", input, &registry).unwrap();
+        assert_eq!(first.path.as_deref(), Some("enemies.prefab"));
+        assert_eq!(first.name(), None);
+        let names: Vec<_> = named.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(names, ["Goblin", "Orc"]);
+        assert_eq!(named[1].1.path.as_deref(), Some("enemies.prefab#Orc"));
+        assert_eq!(named[1].1.steps.len(), 2);
+    }
+
+    #[test]
+    fn share_types() {
+        let loader = Arc::new(RwLock::new(PrefabRegistry::default()));
+        let mut registry = registry(&[]);
+        registry.share_types(loader.clone());
+        assert!(loader.read().unwrap().get_type_data("Transform").is_some());
+
+        // Types registered later are shared right away.
+        registry.register_type::<Visibility>();
+        assert!(loader.read().unwrap().get_type_data("Visibility").is_some());
+    }
+
+    /// The translation of the first `Transform` in a cached prefab.
+    fn translation(registry: &PrefabRegistry, name: &str) -> Vec3 {
+        let prefab = registry.cached_prefab(name).unwrap();
+        let mut transform = Transform::default();
+        for step in prefab.steps.iter() {
+            if let PrefabBuildStep::AddComponent(component) = step {
+                if component.type_name == "Transform" {
+                    transform.apply(&*component.reflect);
+                    break;
+                }
+            }
+        }
+        transform.translation
+    }
+
+    #[test]
+    fn reload_changed() {
+        let source = TestSource::default();
+        source.set("a.prefab", "{ Transform }", 1);
+        source.set("b.prefab", ": \"a.prefab\" { Visibility }", 1);
+        source.set("c.prefab", "{ LoadPrefab!(name: \"a.prefab\") }", 1);
+        let mut registry = registry(&[]);
+        registry.register_type::<Visibility>();
+        registry.add_source(source.clone());
+        registry.load("b.prefab").unwrap();
+        registry.load("c.prefab").unwrap();
+
+        // Files are only checked once the registry is watching for changes.
+        source.set(
+            "a.prefab",
+            "{ Transform { translation: Vec3 { x: 1.0 } } }",
+            2,
+        );
+        assert!(registry.reload_changed().is_empty());
+
+        // Prefabs that inherit from the changed prefab are reloaded too. Prefabs that load
+        // it with `LoadPrefab` read the new version when they're applied.
+        registry.watch_for_changes();
+        assert_eq!(registry.reload_changed(), ["a.prefab", "b.prefab"]);
+        assert_eq!(translation(&registry, "a.prefab").x, 1.0);
+        assert_eq!(translation(&registry, "b.prefab").x, 1.0);
+
+        registry.force_check();
+        assert!(registry.reload_changed().is_empty());
+
+        // Files are checked at most once per interval.
+        source.set(
+            "a.prefab",
+            "{ Transform { translation: Vec3 { x: 2.0 } } }",
+            3,
+        );
+        assert!(registry.reload_changed().is_empty());
+
+        // Prefabs that fail to reload keep their previous version, which prefabs that
+        // inherit from them are reloaded with.
+        source.set("a.prefab", "{ Transform {", 4);
+        registry.force_check();
+        assert_eq!(registry.reload_changed(), ["b.prefab"]);
+        assert_eq!(translation(&registry, "a.prefab").x, 1.0);
+    }
+
+    #[test]
+    fn reload_after_sources_change() {
+        let first = TestSource::default();
+        first.set("a.prefab", "{ Transform }", 1);
+        let mut registry = registry(&[]);
+        registry.add_source(first.clone());
+        registry.watch_for_changes();
+        registry.load("a.prefab").unwrap();
+
+        // Prefabs are only checked against the source they were read from, so they aren't
+        // reloaded once it's removed.
+        let second = TestSource::default();
+        second.set("a.prefab", "{ Transform }", 5);
+        registry.clear_sources();
+        registry.add_source(MemoryPrefabSource::new());
+        registry.add_source(second);
+        assert!(registry.reload_changed().is_empty());
+        first.set("a.prefab", "{ Transform }", 2);
+        registry.force_check();
+        assert!(registry.reload_changed().is_empty());
+    }
+
+    #[test]
+    fn load_cached_after_unload() {
+        let mut registry = registry(&[
+            ("a.prefab", "{ Transform }"),
+            ("b.prefab", "{ LoadPrefab!(name: \"a.prefab\") }"),
+        ]);
+        registry.load("b.prefab").unwrap();
+
+        // Cached prefabs are returned without reading the prefabs they depend on.
+        registry.unload_prefab("a.prefab");
+        registry.clear_sources();
+        assert!(registry.load("b.prefab").is_ok());
+        assert!(matches!(
+            registry.load("a.prefab").unwrap_err(),
+            LoadPrefabError::FileReadError(_, _)
+        ));
+
+        // Cycles through cached prefabs are still found.
+        registry.add_source(MemoryPrefabSource::from_iter([(
+            "a.prefab",
+            "{ LoadPrefab!(name: \"b.prefab\") }",
+        )]));
+        match registry.load("a.prefab").unwrap_err() {
+            LoadPrefabError::Cycle(chain) => {
+                assert_eq!(chain, ["a.prefab", "b.prefab", "a.prefab"])
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        assert!(registry.cached_prefab("a.prefab").is_none());
+    }
+}
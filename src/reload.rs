@@ -0,0 +1,256 @@
+use bevy::{
+    asset::{AssetPath, HandleId},
+    ecs::system::Command,
+    prelude::*,
+    reflect::DynamicStruct,
+    utils::{HashMap, HashSet},
+};
+use std::sync::Arc;
+
+use crate::{bevy_commands::PrefabChild, prefab::Prefab, InsertPrefab, PrefabRegistry};
+
+/// The prefabs that were inserted into an entity, in the order they were inserted.
+///
+/// Used to re-apply prefabs to the entity when they're reloaded. Only prefabs that
+/// were loaded from a file are included.
+#[derive(Component, Debug, Default)]
+pub struct PrefabInstance {
+    prefabs: Vec<String>,
+    /// The arguments the prefabs were inserted with, if any were given.
+    args: HashMap<String, Arc<DynamicStruct>>,
+}
+
+impl PrefabInstance {
+    /// The names of the prefabs inserted into the entity.
+    pub fn prefabs(&self) -> &[String] {
+        &self.prefabs
+    }
+}
+
+/// Sent when a prefab is reloaded because it's file, or the file of a prefab it loads
+/// with `LoadPrefab`, changed.
+#[derive(Debug, Clone)]
+pub struct PrefabReloaded {
+    /// The name of the reloaded prefab.
+    pub name: String,
+}
+
+/// Records that a prefab was inserted into an entity.
+pub(crate) struct AddPrefabInstance {
+    pub entity: Entity,
+    pub name: String,
+    pub args: Option<Arc<DynamicStruct>>,
+}
+
+impl Command for AddPrefabInstance {
+    fn write(self, world: &mut World) {
+        let mut entity = match world.get_entity_mut(self.entity) {
+            Some(entity) => entity,
+            None => return,
+        };
+        if entity.get::<PrefabInstance>().is_none() {
+            entity.insert(PrefabInstance::default());
+        }
+        let mut instance = entity.get_mut::<PrefabInstance>().unwrap();
+        match self.args {
+            Some(args) => instance.args.insert(self.name.clone(), args),
+            None => instance.args.remove(&self.name),
+        };
+        if !instance.prefabs.contains(&self.name) {
+            instance.prefabs.push(self.name);
+        }
+    }
+}
+
+/// Reload changed prefab files if the [PrefabRegistry] is watching for changes.
+pub(crate) fn watch_prefab_files(
+    mut registry: ResMut<PrefabRegistry>,
+    assets: Option<Res<Assets<Prefab>>>,
+    mut events: EventWriter<PrefabReloaded>,
+) {
+    let changed = registry.reload_changed();
+    if changed.is_empty() {
+        return;
+    }
+
+    let mut prefabs: Vec<&Prefab> = registry.cached_prefabs().map(|p| p.as_ref()).collect();
+    if let Some(assets) = &assets {
+        prefabs.extend(assets.iter().map(|(_, prefab)| prefab));
+    }
+    events.send_batch(reloaded_events(changed, &prefabs));
+}
+
+/// Send reload events for prefab assets that were reloaded by the `AssetServer`.
+pub(crate) fn watch_prefab_assets(
+    mut asset_events: EventReader<AssetEvent<Prefab>>,
+    assets: Res<Assets<Prefab>>,
+    registry: Res<PrefabRegistry>,
+    server: Res<AssetServer>,
+    mut events: EventWriter<PrefabReloaded>,
+) {
+    let changed: Vec<_> = asset_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => assets.get(handle)?.path.clone(),
+            _ => None,
+        })
+        .collect();
+    if changed.is_empty() {
+        return;
+    }
+
+    // Base prefabs are merged into the prefabs that inherit from them when they're loaded,
+    // so those need to be loaded again.
+    for (_, prefab) in assets.iter() {
+        if let Some(path) = &prefab.path {
+            if changed.iter().any(|name| prefab.inherits_from(name)) {
+                server.reload_asset(path.as_str());
+            }
+        }
+    }
+
+    let mut prefabs: Vec<&Prefab> = assets.iter().map(|(_, prefab)| prefab).collect();
+    prefabs.extend(registry.cached_prefabs().map(|p| p.as_ref()));
+    events.send_batch(reloaded_events(changed, &prefabs));
+}
+
+/// Events for the changed prefabs and every prefab that loads them, directly or indirectly.
+fn reloaded_events(changed: Vec<String>, prefabs: &[&Prefab]) -> Vec<PrefabReloaded> {
+    let mut reloaded = changed.clone();
+    let mut remaining = changed;
+    while let Some(name) = remaining.pop() {
+        for prefab in prefabs {
+            let path = match &prefab.path {
+                Some(path) => path,
+                None => continue,
+            };
+            if !reloaded.contains(path) && prefab.dependencies().contains(&name) {
+                reloaded.push(path.clone());
+                remaining.push(path.clone());
+            }
+        }
+    }
+
+    reloaded
+        .into_iter()
+        .map(|name| PrefabReloaded { name })
+        .collect()
+}
+
+/// Re-apply reloaded prefabs to the entities they were inserted into.
+pub(crate) fn reapply_reloaded_prefabs(
+    mut commands: Commands,
+    mut events: EventReader<PrefabReloaded>,
+    instances: Query<(Entity, &PrefabInstance, Option<&Children>)>,
+    prefab_children: Query<(), With<PrefabChild>>,
+    mut registry: ResMut<PrefabRegistry>,
+    assets: Option<Res<Assets<Prefab>>>,
+) {
+    let reloaded: HashSet<_> = events.iter().map(|e| e.name.clone()).collect();
+    if reloaded.is_empty() {
+        return;
+    }
+
+    for (entity, instance, children) in instances.iter() {
+        if !instance.prefabs.iter().any(|name| reloaded.contains(name)) {
+            continue;
+        }
+
+        // Children spawned by the prefabs are spawned again.
+        for &child in children.into_iter().flatten() {
+            if prefab_children.contains(child) {
+                commands.entity(child).despawn_recursive();
+            }
+        }
+
+        // Every prefab on the entity is re-applied in order, so later prefabs still
+        // override earlier ones.
+        for name in instance.prefabs.iter() {
+            let args = instance.args.get(name).cloned();
+            let id = HandleId::from(AssetPath::from(name.as_str()));
+            let asset = assets.as_ref().and_then(|a| a.get(&Handle::weak(id)));
+            if let Some(prefab) = asset {
+                prefab.insert_into(&mut commands.entity(entity), args);
+                continue;
+            }
+
+            match registry.load(name) {
+                Ok(prefab) => {
+                    let prefab = prefab.clone();
+                    prefab.insert_into(&mut commands.entity(entity), args);
+                }
+                Err(e) => error!("Error re-applying prefab - {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::{ecs::system::CommandQueue, prelude::*};
+
+    use crate::{
+        build_commands::LoadPrefab, plugin::LazyPrefabsMinimalPlugin, source::test::TestSource,
+        PrefabInstance, PrefabRegistry, PrefabReloaded, SpawnPrefabCommands,
+    };
+
+    #[test]
+    fn reapply_reloaded() {
+        let source = TestSource::default();
+        source.set(
+            "unit.prefab",
+            "{ Transform, children: [{ Visibility }] }",
+            1,
+        );
+        source.set("squad.prefab", "{ LoadPrefab!(name: \"unit.prefab\") }", 1);
+
+        let mut app = App::new();
+        app.add_plugin(LazyPrefabsMinimalPlugin);
+        let mut registry = app.world.resource_mut::<PrefabRegistry>();
+        registry.register_type::<Transform>();
+        registry.register_type::<Visibility>();
+        registry.register_build_command::<LoadPrefab>();
+        registry.clear_sources();
+        registry.add_source(source.clone());
+        registry.watch_for_changes();
+        let unit = registry.load("unit.prefab").unwrap().clone();
+        let squad = registry.load("squad.prefab").unwrap().clone();
+
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &app.world);
+        let unit_entity = commands.spawn_empty().insert_prefab(&unit).id();
+        let squad_entity = commands.spawn_empty().insert_prefab(&squad).id();
+        queue.apply(&mut app.world);
+        app.update();
+
+        let instance = app.world.get::<PrefabInstance>(squad_entity).unwrap();
+        assert_eq!(instance.prefabs(), ["squad.prefab"]);
+        let child = app.world.get::<Children>(unit_entity).unwrap()[0];
+
+        source.set(
+            "unit.prefab",
+            "{ Transform { translation: Vec3 { x: 3.0 } }, children: [{ Visibility }] }",
+            2,
+        );
+        app.world.resource_mut::<PrefabRegistry>().force_check();
+        app.update();
+
+        let events = app.world.resource::<Events<PrefabReloaded>>();
+        let mut reloaded: Vec<_> = events
+            .get_reader()
+            .iter(events)
+            .map(|e| e.name.clone())
+            .collect();
+        reloaded.sort();
+        assert_eq!(reloaded, ["squad.prefab", "unit.prefab"]);
+
+        // Entities that load the prefab with `LoadPrefab` are updated too.
+        for entity in [unit_entity, squad_entity] {
+            let transform = app.world.get::<Transform>(entity).unwrap();
+            assert_eq!(transform.translation.x, 3.0);
+            assert_eq!(app.world.get::<Children>(entity).unwrap().len(), 1);
+        }
+        // The prefab's children are spawned again.
+        assert!(app.world.get_entity(child).is_none());
+    }
+}
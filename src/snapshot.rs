@@ -0,0 +1,256 @@
+//! Builds [Prefab]s from entities in a [World].
+
+use std::sync::Arc;
+
+use bevy::{
+    prelude::*,
+    reflect::{DynamicStruct, ReflectRef},
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    bevy_commands::map_entities,
+    prefab::{Prefab, PrefabBuildStep, PrefabComponent},
+    PrefabRegistry,
+};
+
+/// Build a prefab from an entity and it's children. See [PrefabRegistry::entity_to_prefab].
+pub(crate) fn snapshot_entity(
+    registry: &PrefabRegistry,
+    world: &World,
+    entity: Entity,
+    omit_defaults: bool,
+) -> Prefab {
+    let mut tree = Vec::new();
+    collect_tree(world, entity, &mut tree);
+
+    // References to entities in the tree are written as named entity references, so they
+    // refer to the entities spawned for the prefab.
+    let mut referenced = HashSet::new();
+    let mut components = HashMap::new();
+    for &entity in tree.iter() {
+        let snapshot = snapshot_components(registry, world, entity, &tree, omit_defaults);
+        for component in snapshot.iter() {
+            referenced.extend(component.entity_refs.iter().cloned());
+        }
+        components.insert(entity, snapshot);
+    }
+
+    build_prefab(world, entity, &mut components, &referenced)
+}
+
+/// The entity and all of it's descendants.
+fn collect_tree(world: &World, entity: Entity, tree: &mut Vec<Entity>) {
+    tree.push(entity);
+    if let Some(children) = world.get::<Children>(entity) {
+        for &child in children.iter() {
+            collect_tree(world, child, tree);
+        }
+    }
+}
+
+/// The name given to an entity in the tree when other entities refer to it.
+fn entity_name(entity: Entity) -> String {
+    format!("entity{}", entity.index())
+}
+
+fn build_prefab(
+    world: &World,
+    entity: Entity,
+    components: &mut HashMap<Entity, Vec<PrefabComponent>>,
+    referenced: &HashSet<String>,
+) -> Prefab {
+    let mut steps = Vec::new();
+    if let Some(children) = world.get::<Children>(entity) {
+        for &child in children.iter() {
+            let child = build_prefab(world, child, components, referenced);
+            steps.push(PrefabBuildStep::SpawnChild(Arc::new(child)));
+        }
+    }
+    for component in components.remove(&entity).unwrap_or_default() {
+        steps.push(PrefabBuildStep::AddComponent(Arc::new(component)));
+    }
+
+    let name = entity_name(entity);
+    Prefab {
+        name: None,
+        path: None,
+        base: None,
+        entity_name: referenced.contains(&name).then_some(name),
+        params: Vec::new(),
+        imports: Vec::new(),
+        steps,
+    }
+}
+
+/// Copy every component of the entity that's registered with the registry.
+fn snapshot_components(
+    registry: &PrefabRegistry,
+    world: &World,
+    entity: Entity,
+    tree: &[Entity],
+    omit_defaults: bool,
+) -> Vec<PrefabComponent> {
+    let entity_ref = world.entity(entity);
+    let mut components = Vec::new();
+    for component_id in entity_ref.archetype().components() {
+        let type_id = match world.components().get_info(component_id) {
+            Some(info) => info.type_id(),
+            None => continue,
+        };
+        // The hierarchy is written as the prefab's children.
+        if type_id == Some(std::any::TypeId::of::<Children>())
+            || type_id == Some(std::any::TypeId::of::<Parent>())
+        {
+            continue;
+        }
+        let type_info = match type_id.and_then(|id| registry.get_type_data_by_id(id)) {
+            Some(type_info) => type_info,
+            None => continue,
+        };
+        let value = match type_info
+            .registration
+            .data::<ReflectComponent>()
+            .and_then(|reflect| reflect.reflect(world, entity))
+        {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let mut value = match omit_defaults {
+            true => changed_fields(value, &*type_info.default_value),
+            false => value.clone_value(),
+        };
+        let mut entity_refs: Vec<String> = Vec::new();
+        map_entities(&mut *value, &mut |entity| {
+            if !tree.contains(&entity) {
+                return entity;
+            }
+            let name = entity_name(entity);
+            let index = match entity_refs.iter().position(|n| *n == name) {
+                Some(index) => index,
+                None => {
+                    entity_refs.push(name);
+                    entity_refs.len() - 1
+                }
+            };
+            PrefabComponent::entity_placeholder(index)
+        });
+
+        components.push(PrefabComponent {
+            type_name: type_info.type_name.clone(),
+            reflect: value,
+            entity_refs,
+            uses_params: false,
+        });
+    }
+    components
+}
+
+/// Copy a value, leaving out struct fields that are the same as in `default`. Structs are
+/// copied as [DynamicStruct]s, which only change the fields they contain when applied.
+fn changed_fields(value: &dyn Reflect, default: &dyn Reflect) -> Box<dyn Reflect> {
+    let (value, default) = match (value.reflect_ref(), default.reflect_ref()) {
+        (ReflectRef::Struct(value), ReflectRef::Struct(default)) => (value, default),
+        _ => return value.clone_value(),
+    };
+
+    let mut changed = DynamicStruct::default();
+    changed.set_name(value.type_name().to_string());
+    for i in 0..value.field_len() {
+        let name = value.name_at(i).unwrap();
+        let field = value.field_at(i).unwrap();
+        match default.field(name) {
+            Some(default) => {
+                if field.reflect_partial_eq(default) == Some(true) {
+                    continue;
+                }
+                // Some types can't be compared directly, but their fields can.
+                let field = changed_fields(field, default);
+                match field.reflect_ref() {
+                    ReflectRef::Struct(field) if field.field_len() == 0 => {}
+                    _ => changed.insert_boxed(name, field),
+                }
+            }
+            None => changed.insert_boxed(name, field.clone_value()),
+        }
+    }
+    Box::new(changed)
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use crate::{bevy_commands::apply_prefab, PrefabRegistry};
+
+    #[derive(Component, Reflect)]
+    #[reflect(Component)]
+    struct Target(Entity);
+
+    impl Default for Target {
+        fn default() -> Self {
+            Target(Entity::from_raw(u32::MAX))
+        }
+    }
+
+    #[test]
+    fn entity_to_prefab() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Transform>();
+        registry.register_type::<Visibility>();
+        registry.register_type::<Target>();
+
+        let mut world = World::new();
+        let child = world.spawn(Visibility { is_visible: false }).id();
+        let entity = world
+            .spawn((
+                Transform::from_xyz(0.0, 2.0, 0.0),
+                Target(child),
+                Name::new("a"),
+            ))
+            .push_children(&[child])
+            .id();
+
+        let full = registry.entity_to_prefab(&world, entity, false);
+        let text = full.to_prefab_string().unwrap();
+        assert!(text.contains(
+            "Transform { translation: Vec3 { x: 0.0, y: 2.0, z: 0.0 }, \
+             rotation: Quat { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }, scale: Vec3 { x: 1.0, y: 1.0, z: 1.0 } }"
+        ));
+        assert!(!text.contains("Name"));
+        let reparsed = registry.load_from_str("full.prefab", &text).unwrap();
+        assert_eq!(reparsed.to_prefab_string().unwrap(), text);
+
+        let prefab = registry.entity_to_prefab(&world, entity, true);
+        let text = prefab.to_prefab_string().unwrap();
+        let children = format!(
+            "    children: [\n        #entity{} {{\n            Visibility {{ is_visible: false }},\n        }},\n    ],\n}}\n",
+            child.index()
+        );
+        assert!(text.ends_with(&children), "{}", text);
+        assert!(
+            text.contains("    Transform { translation: Vec3 { y: 2.0 } },\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains(&format!("    Target(@entity{}),\n", child.index())));
+
+        let reparsed = registry
+            .load_from_str("snapshot.prefab", &text)
+            .unwrap()
+            .clone();
+        world.insert_resource(registry);
+        let copy = world.spawn_empty().id();
+        apply_prefab(&mut world, copy, &reparsed, None);
+
+        assert_eq!(
+            world.get::<Transform>(copy).unwrap().translation,
+            Vec3::new(0.0, 2.0, 0.0)
+        );
+        let copy_child = world.get::<Children>(copy).unwrap()[0];
+        assert_ne!(copy_child, child);
+        assert_eq!(world.get::<Target>(copy).unwrap().0, copy_child);
+        assert!(!world.get::<Visibility>(copy_child).unwrap().is_visible);
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::{
+    asset::{AssetIo, AssetIoError, FileAssetIo},
+    utils::HashMap,
+};
+use futures_lite::future;
+
+/// Somewhere the [PrefabRegistry](crate::PrefabRegistry) can read *.prefab* files from.
+///
+/// Sources are searched in reverse of the order they were added with
+/// [PrefabRegistry::add_source](crate::PrefabRegistry::add_source), so later sources
+/// override earlier ones. By default the registry reads from the *assets/* folder.
+///
+/// Closures that take a prefab name and return it's text are also sources.
+///
+/// ## Example
+///
+/// ```
+/// use bevy::asset::AssetIoError;
+/// use bevy_lazy_prefabs::*;
+///
+/// let mut registry = PrefabRegistry::default();
+/// registry.add_source(FilePrefabSource::new("tests/fixtures"));
+/// registry.add_source(|name: &str| match name {
+///     "origin.prefab" => Ok("{ Transform }".to_string()),
+///     _ => Err(AssetIoError::NotFound(name.into())),
+/// });
+/// ```
+pub trait PrefabSource: Send + Sync {
+    /// Read the text of a prefab. Returns [AssetIoError::NotFound] if the source doesn't
+    /// have the prefab, so the next source is searched.
+    fn read(&self, name: &str) -> Result<String, AssetIoError>;
+
+    /// When the prefab was last modified, if it's known. Used to reload prefabs when
+    /// the [PrefabRegistry](crate::PrefabRegistry) is watching for changes.
+    fn modified(&self, _name: &str) -> Option<SystemTime> {
+        None
+    }
+}
+
+impl<F> PrefabSource for F
+where
+    F: Fn(&str) -> Result<String, AssetIoError> + Send + Sync,
+{
+    fn read(&self, name: &str) -> Result<String, AssetIoError> {
+        self(name)
+    }
+}
+
+/// Reads prefabs from a folder.
+pub struct FilePrefabSource {
+    io: FileAssetIo,
+}
+
+impl FilePrefabSource {
+    /// Read prefabs from the given folder. Relative paths are relative to the same base
+    /// path as bevy's asset folder, ie: the project folder when run with cargo.
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        FilePrefabSource {
+            io: FileAssetIo::new(root, false),
+        }
+    }
+
+    /// The full path of the folder prefabs are read from.
+    pub fn root_path(&self) -> &PathBuf {
+        self.io.root_path()
+    }
+}
+
+impl Default for FilePrefabSource {
+    fn default() -> Self {
+        FilePrefabSource::new("assets/")
+    }
+}
+
+impl PrefabSource for FilePrefabSource {
+    fn read(&self, name: &str) -> Result<String, AssetIoError> {
+        let data = future::block_on(self.io.load_path(Path::new(name)))?;
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        std::fs::metadata(self.io.root_path().join(name))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+/// Prefabs stored in memory, ie: embedded in the executable or received over the network.
+#[derive(Default)]
+pub struct MemoryPrefabSource {
+    prefabs: HashMap<String, String>,
+}
+
+impl MemoryPrefabSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a prefab, replacing any prefab with the same name.
+    pub fn insert(&mut self, name: impl Into<String>, text: impl Into<String>) -> &mut Self {
+        self.prefabs.insert(name.into(), text.into());
+        self
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for MemoryPrefabSource {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        MemoryPrefabSource {
+            prefabs: iter
+                .into_iter()
+                .map(|(name, text)| (name.into(), text.into()))
+                .collect(),
+        }
+    }
+}
+
+impl PrefabSource for MemoryPrefabSource {
+    fn read(&self, name: &str) -> Result<String, AssetIoError> {
+        self.prefabs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| AssetIoError::NotFound(name.into()))
+    }
+}
+
+/// Identifies a source in [PrefabSources]. Ids aren't reused, so they keep referring to the
+/// same source when other sources are added or removed.
+pub(crate) type SourceId = usize;
+
+/// The ordered list of sources a [PrefabRegistry](crate::PrefabRegistry) reads from.
+pub(crate) struct PrefabSources {
+    sources: Vec<(SourceId, Box<dyn PrefabSource>)>,
+    next_id: SourceId,
+}
+
+impl Default for PrefabSources {
+    fn default() -> Self {
+        PrefabSources {
+            sources: vec![(0, Box::new(FilePrefabSource::default()))],
+            next_id: 1,
+        }
+    }
+}
+
+impl PrefabSources {
+    pub fn push(&mut self, source: Box<dyn PrefabSource>) {
+        self.sources.push((self.next_id, source));
+        self.next_id += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.sources.clear();
+    }
+
+    /// Read a prefab from the last source that has it, returning the text and the id of
+    /// the source it was read from.
+    pub fn read(&self, name: &str) -> Result<(String, SourceId), AssetIoError> {
+        for (id, source) in self.sources.iter().rev() {
+            match source.read(name) {
+                Err(AssetIoError::NotFound(_)) => continue,
+                result => return result.map(|text| (text, *id)),
+            }
+        }
+        Err(AssetIoError::NotFound(name.into()))
+    }
+
+    /// When a prefab was last modified in the given source. [None] if the source was
+    /// removed.
+    pub fn modified(&self, id: SourceId, name: &str) -> Option<SystemTime> {
+        let (_, source) = self
+            .sources
+            .iter()
+            .find(|(source_id, _)| *source_id == id)?;
+        source.modified(name)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    use bevy::{asset::AssetIoError, utils::HashMap};
+
+    use super::{FilePrefabSource, MemoryPrefabSource, PrefabSource, PrefabSources};
+
+    /// A source whose prefabs are changed by tests, along with the time they changed.
+    #[derive(Clone, Default)]
+    pub(crate) struct TestSource(Arc<Mutex<HashMap<String, (String, SystemTime)>>>);
+
+    impl TestSource {
+        /// Set the text of a prefab, modified the given number of seconds after the epoch.
+        pub fn set(&self, name: &str, text: &str, secs: u64) {
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            let mut prefabs = self.0.lock().unwrap();
+            prefabs.insert(name.to_string(), (text.to_string(), modified));
+        }
+    }
+
+    impl PrefabSource for TestSource {
+        fn read(&self, name: &str) -> Result<String, AssetIoError> {
+            let prefabs = self.0.lock().unwrap();
+            match prefabs.get(name) {
+                Some((text, _)) => Ok(text.clone()),
+                None => Err(AssetIoError::NotFound(name.into())),
+            }
+        }
+
+        fn modified(&self, name: &str) -> Option<SystemTime> {
+            let prefabs = self.0.lock().unwrap();
+            prefabs.get(name).map(|(_, modified)| *modified)
+        }
+    }
+
+    #[test]
+    fn memory_source() {
+        let mut source = MemoryPrefabSource::new();
+        source.insert("a.prefab", "{ Transform }");
+        source.insert("a.prefab", "{ Visibility }");
+        assert_eq!(source.read("a.prefab").unwrap(), "{ Visibility }");
+        assert!(matches!(
+            source.read("b.prefab"),
+            Err(AssetIoError::NotFound(_))
+        ));
+        assert!(source.modified("a.prefab").is_none());
+    }
+
+    #[test]
+    fn file_source() {
+        let root = std::env::temp_dir().join(format!("lazy_prefabs_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("units")).unwrap();
+        std::fs::write(root.join("units/knight.prefab"), "{ Transform }").unwrap();
+
+        let source = FilePrefabSource::new(&root);
+        assert_eq!(source.root_path(), &root);
+        assert_eq!(source.read("units/knight.prefab").unwrap(), "{ Transform }");
+        assert!(source.modified("units/knight.prefab").is_some());
+        assert!(matches!(
+            source.read("knight.prefab"),
+            Err(AssetIoError::NotFound(_))
+        ));
+        assert!(source.modified("knight.prefab").is_none());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn source_order() {
+        let mut sources = PrefabSources::default();
+        sources.clear();
+        let first: MemoryPrefabSource = [("a.prefab", "first"), ("b.prefab", "first")]
+            .into_iter()
+            .collect();
+        sources.push(Box::new(first));
+        let second: MemoryPrefabSource = [("a.prefab", "second")].into_iter().collect();
+        sources.push(Box::new(second));
+
+        // Later sources are searched first.
+        assert_eq!(sources.read("a.prefab").unwrap(), ("second".to_string(), 2));
+        assert_eq!(sources.read("b.prefab").unwrap(), ("first".to_string(), 1));
+        assert!(matches!(
+            sources.read("c.prefab"),
+            Err(AssetIoError::NotFound(_))
+        ));
+
+        // Errors other than a missing prefab stop the search.
+        sources.push(Box::new(|name: &str| match name {
+            "b.prefab" => Err(AssetIoError::Io(std::io::ErrorKind::Other.into())),
+            _ => Err(AssetIoError::NotFound(name.into())),
+        }));
+        assert!(matches!(sources.read("b.prefab"), Err(AssetIoError::Io(_))));
+        assert_eq!(sources.read("a.prefab").unwrap().0, "second");
+
+        // Ids keep referring to the same source after sources are removed.
+        let source = TestSource::default();
+        source.set("a.prefab", "third", 1);
+        sources.clear();
+        sources.push(Box::new(source));
+        let (_, id) = sources.read("a.prefab").unwrap();
+        assert_eq!(id, 4);
+        assert!(sources.modified(id, "a.prefab").is_some());
+        assert!(sources.modified(2, "a.prefab").is_none());
+    }
+}
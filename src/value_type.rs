@@ -0,0 +1,590 @@
+use std::{any::TypeId, sync::Arc};
+
+use bevy::{
+    math::{Quat, Vec2, Vec3},
+    prelude::Color,
+    reflect::{Reflect, TypeInfo as ReflectTypeInfo, VariantInfo},
+    utils::get_short_name,
+};
+
+use crate::{
+    manifest::{ManifestKind, ManifestType, ManifestVariantKind},
+    parse::LoadPrefabError,
+    registry::{PrefabRegistry, ReflectType},
+};
+
+/// The type a value in a *.prefab* file is expected to be parsed into.
+///
+/// Expected types are resolved from the reflected type info of types registered with the
+/// [PrefabRegistry]. Primitive, `String`, `Option` and `Vec` types are resolved from their
+/// type name, so they don't need to be registered. Types described by a
+/// [TypeManifest](crate::TypeManifest) are resolved from their description.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExpectedType {
+    /// The path to the value, used in error messages. ie: `Transform.translation.x`.
+    pub path: String,
+    /// The full type name of the expected type, if it's known.
+    pub type_name: Option<&'static str>,
+    /// The reflected type info of the expected type, if it was registered.
+    pub info: Option<&'static ReflectTypeInfo>,
+    /// The description of the expected type, if it was described by a manifest.
+    pub described: Option<Arc<ManifestType>>,
+}
+
+impl ExpectedType {
+    /// A value whose type is unknown, ie: a build command property.
+    pub fn unknown(path: impl Into<String>) -> Self {
+        ExpectedType {
+            path: path.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A value of the given type.
+    pub fn named(path: impl Into<String>, type_name: &'static str) -> Self {
+        ExpectedType {
+            path: path.into(),
+            type_name: Some(type_name),
+            ..Default::default()
+        }
+    }
+
+    /// A value of a type registered with the [PrefabRegistry].
+    pub fn registered(path: impl Into<String>, type_info: &crate::registry::TypeInfo) -> Self {
+        ExpectedType {
+            path: path.into(),
+            type_name: Some(type_info.registration.type_name()),
+            info: Some(type_info.registration.type_info()),
+            described: None,
+        }
+    }
+
+    /// A value of a type described by a [TypeManifest](crate::TypeManifest).
+    pub fn described(path: impl Into<String>, described: &Arc<ManifestType>) -> Self {
+        ExpectedType {
+            path: path.into(),
+            described: Some(described.clone()),
+            ..Default::default()
+        }
+    }
+
+    fn resolve(
+        registry: &PrefabRegistry,
+        path: String,
+        type_name: &'static str,
+        type_id: Option<TypeId>,
+    ) -> Self {
+        let type_data = match type_id {
+            Some(id) => registry.get_type_data_by_id(id),
+            None => registry
+                .get_type_data(&get_short_name(type_name))
+                .filter(|t| t.registration.type_name() == type_name),
+        };
+        let info = type_data.map(|t| t.registration.type_info());
+        ExpectedType {
+            path,
+            type_name: Some(type_name),
+            info,
+            described: None,
+        }
+    }
+
+    /// The expected type of a field of a described type, from the field's type name.
+    fn describe_field(registry: &PrefabRegistry, path: String, type_name: Option<&str>) -> Self {
+        type_name
+            .and_then(|t| Self::from_name(registry, path.clone(), t))
+            .unwrap_or_else(|| Self::unknown(path))
+    }
+
+    /// The type of a prefab parameter, from the type name written in the prefab. Returns
+    /// [None] if the type isn't registered, described or built in.
+    pub fn from_name(registry: &PrefabRegistry, path: String, type_name: &str) -> Option<Self> {
+        if let Some(t) = registry.get_type_data(type_name) {
+            return Some(Self::registered(path, t));
+        }
+        if let Some(t) = registry.get_described_type(type_name) {
+            return Some(Self::described(path, t));
+        }
+        let full_name = match type_name {
+            "String" => "alloc::string::String",
+            "Vec2" => std::any::type_name::<Vec2>(),
+            "Vec3" => std::any::type_name::<Vec3>(),
+            "Quat" => std::any::type_name::<Quat>(),
+            "Color" => std::any::type_name::<Color>(),
+            name => PRIMITIVES.iter().find(|p| **p == name).copied()?,
+        };
+        Some(Self::named(path, full_name))
+    }
+
+    /// The expected type of a named field on a struct.
+    pub fn field(&self, registry: &PrefabRegistry, name: &str) -> Self {
+        let path = format!("{}.{}", self.path, name);
+        if let Some(ManifestKind::Struct { fields }) = self.described_kind() {
+            let field = fields.iter().find(|f| f.name == name);
+            return Self::describe_field(registry, path, field.map(|f| f.type_name.as_str()));
+        }
+        match self.info {
+            Some(ReflectTypeInfo::Struct(info)) => match info.field(name) {
+                Some(field) => {
+                    Self::resolve(registry, path, field.type_name(), Some(field.type_id()))
+                }
+                None => Self::unknown(path),
+            },
+            _ => Self::unknown(path),
+        }
+    }
+
+    /// The expected type of a positional value in a tuple, list or array.
+    pub fn element(&self, registry: &PrefabRegistry, index: usize) -> Self {
+        let path = format!("{}[{}]", self.path, index);
+        match self.described_kind() {
+            Some(ManifestKind::TupleStruct { fields } | ManifestKind::Tuple { fields }) => {
+                let field = fields.get(index).map(String::as_str);
+                return Self::describe_field(registry, path, field);
+            }
+            Some(ManifestKind::Value) => return self.clone(),
+            Some(_) => return Self::unknown(path),
+            None => {}
+        }
+        let (type_name, type_id) = match self.info {
+            Some(ReflectTypeInfo::TupleStruct(info)) => match info.field_at(index) {
+                Some(field) => (field.type_name(), Some(field.type_id())),
+                None => return Self::unknown(path),
+            },
+            Some(ReflectTypeInfo::Tuple(info)) => match info.field_at(index) {
+                Some(field) => (field.type_name(), Some(field.type_id())),
+                None => return Self::unknown(path),
+            },
+            Some(ReflectTypeInfo::List(info)) => (info.item_type_name(), Some(info.item_type_id())),
+            Some(ReflectTypeInfo::Array(info)) => {
+                (info.item_type_name(), Some(info.item_type_id()))
+            }
+            // A value type is written as it's own single value, ie: `String("hi")`.
+            Some(ReflectTypeInfo::Value(_)) => return self.clone(),
+            Some(_) => return Self::unknown(path),
+            None => match self
+                .type_name
+                .and_then(|n| generic_arg(n, "alloc::vec::Vec<"))
+            {
+                Some(item) => (item, None),
+                None => return Self::unknown(path),
+            },
+        };
+        Self::resolve(registry, path, type_name, type_id)
+    }
+
+    /// The expected type of the start and end of a range.
+    pub fn range_element(&self) -> Self {
+        ExpectedType {
+            path: self.path.clone(),
+            type_name: self
+                .type_name
+                .and_then(|n| generic_arg(n, "core::ops::range::Range<")),
+            ..Default::default()
+        }
+    }
+
+    /// The expected type of the keys in a map.
+    pub fn map_key(&self, registry: &PrefabRegistry) -> Self {
+        let path = format!("{}.<key>", self.path);
+        match self.info {
+            Some(ReflectTypeInfo::Map(info)) => Self::resolve(
+                registry,
+                path,
+                info.key_type_name(),
+                Some(info.key_type_id()),
+            ),
+            _ => Self::unknown(path),
+        }
+    }
+
+    /// The expected type of a value in a map.
+    pub fn map_value(&self, registry: &PrefabRegistry, key: &str) -> Self {
+        let path = format!("{}[{}]", self.path, key);
+        match self.info {
+            Some(ReflectTypeInfo::Map(info)) => Self::resolve(
+                registry,
+                path,
+                info.value_type_name(),
+                Some(info.value_type_id()),
+            ),
+            _ => Self::unknown(path),
+        }
+    }
+
+    /// The expected type of a named field on an enum's struct variant.
+    pub fn variant_field(&self, registry: &PrefabRegistry, variant: &str, name: &str) -> Self {
+        let path = format!("{}::{}.{}", self.path, variant, name);
+        if let Some(ManifestVariantKind::Struct { fields }) = self.described_variant(variant) {
+            let field = fields.iter().find(|f| f.name == name);
+            return Self::describe_field(registry, path, field.map(|f| f.type_name.as_str()));
+        }
+        match self.variant_info(variant) {
+            Some(VariantInfo::Struct(info)) => match info.field(name) {
+                Some(field) => {
+                    Self::resolve(registry, path, field.type_name(), Some(field.type_id()))
+                }
+                None => Self::unknown(path),
+            },
+            _ => Self::unknown(path),
+        }
+    }
+
+    /// The expected type of a positional value on an enum's tuple variant.
+    pub fn variant_element(&self, registry: &PrefabRegistry, variant: &str, index: usize) -> Self {
+        let path = format!("{}::{}[{}]", self.path, variant, index);
+        if self.described.is_some() {
+            let field = match self.described_variant(variant) {
+                Some(ManifestVariantKind::Tuple { fields }) => fields.get(index),
+                _ => None,
+            };
+            return Self::describe_field(registry, path, field.map(String::as_str));
+        }
+        match self.variant_info(variant) {
+            Some(VariantInfo::Tuple(info)) => match info.field_at(index) {
+                Some(field) => {
+                    Self::resolve(registry, path, field.type_name(), Some(field.type_id()))
+                }
+                None => Self::unknown(path),
+            },
+            Some(_) => Self::unknown(path),
+            None => {
+                let inner = self
+                    .type_name
+                    .and_then(|n| generic_arg(n, "core::option::Option<"));
+                match inner {
+                    Some(inner) if variant == "Some" && index == 0 => {
+                        Self::resolve(registry, path, inner, None)
+                    }
+                    _ => Self::unknown(path),
+                }
+            }
+        }
+    }
+
+    /// Ensure a struct has a field with the given name. Fields of types that aren't
+    /// registered structs aren't checked.
+    pub fn check_field(&self, name: &str) -> Result<(), LoadPrefabError> {
+        if let Some(ManifestKind::Struct { fields }) = self.described_kind() {
+            let fields: Vec<_> = fields.iter().map(|f| f.name.as_str()).collect();
+            return check_field_name(&self.short_name(), name, &fields);
+        }
+        match self.info {
+            Some(ReflectTypeInfo::Struct(info)) => {
+                let fields: Vec<_> = info.iter().map(|f| f.name()).collect();
+                check_field_name(&self.short_name(), name, &fields)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Ensure an enum's struct variant has a field with the given name.
+    pub fn check_variant_field(&self, variant: &str, name: &str) -> Result<(), LoadPrefabError> {
+        let type_name = format!("{}::{}", self.short_name(), variant);
+        if let Some(ManifestVariantKind::Struct { fields }) = self.described_variant(variant) {
+            let fields: Vec<_> = fields.iter().map(|f| f.name.as_str()).collect();
+            return check_field_name(&type_name, name, &fields);
+        }
+        match self.variant_info(variant) {
+            Some(VariantInfo::Struct(info)) => {
+                let fields: Vec<_> = info.iter().map(|f| f.name()).collect();
+                check_field_name(&type_name, name, &fields)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn short_name(&self) -> String {
+        match &self.described {
+            Some(described) => described.name.clone(),
+            None => self.type_name.map(get_short_name).unwrap_or_default(),
+        }
+    }
+
+    fn described_kind(&self) -> Option<&ManifestKind> {
+        self.described.as_ref().map(|t| &t.kind)
+    }
+
+    /// The kind of a variant of a described enum.
+    pub fn described_variant(&self, variant: &str) -> Option<&ManifestVariantKind> {
+        match self.described_kind() {
+            Some(ManifestKind::Enum { variants }) => {
+                variants.iter().find(|v| v.name == variant).map(|v| &v.kind)
+            }
+            _ => None,
+        }
+    }
+
+    fn variant_info(&self, variant: &str) -> Option<&'static VariantInfo> {
+        match self.info {
+            Some(ReflectTypeInfo::Enum(info)) => info.variant(variant),
+            _ => None,
+        }
+    }
+
+    /// The expected primitive type name, ie: `u8` or `f64`.
+    pub fn primitive(&self) -> Option<&'static str> {
+        self.type_name.filter(|n| is_primitive(n))
+    }
+
+    /// Ensure a parsed value can be applied to the expected type.
+    ///
+    /// Primitive and value types must match exactly, other types must be the same
+    /// kind of type, ie: a struct can't be applied to a list.
+    pub fn check(&self, value: &dyn Reflect, text: &str) -> Result<(), LoadPrefabError> {
+        if let Some(kind) = self.described_kind() {
+            return match described_kind_of(kind) {
+                Some(kind) if ReflectType::from(value.reflect_ref()) != kind => {
+                    Err(self.mismatch(text))
+                }
+                _ => Ok(()),
+            };
+        }
+        let matches = match (self.info, self.primitive()) {
+            (Some(ReflectTypeInfo::Dynamic(_)), _) => true,
+            (Some(ReflectTypeInfo::Value(info)), _) => value.type_name() == info.type_name(),
+            (Some(info), _) => ReflectType::from(value.reflect_ref()) == kind_of(info),
+            (None, Some(name)) => value.type_name() == name,
+            (None, None) => match self.type_name {
+                Some(name) if is_built_in(name) => value.type_name() == name,
+                _ => true,
+            },
+        };
+
+        if matches {
+            Ok(())
+        } else {
+            Err(self.mismatch(text))
+        }
+    }
+
+    /// An error for a value that couldn't be parsed as the expected type.
+    pub fn mismatch(&self, text: &str) -> LoadPrefabError {
+        LoadPrefabError::MismatchedValueType(self.path.clone(), self.short_name(), text.to_string())
+    }
+}
+
+fn kind_of(info: &ReflectTypeInfo) -> ReflectType {
+    match info {
+        ReflectTypeInfo::Struct(_) => ReflectType::Struct,
+        ReflectTypeInfo::TupleStruct(_) => ReflectType::TupleStruct,
+        ReflectTypeInfo::Tuple(_) => ReflectType::Tuple,
+        ReflectTypeInfo::List(_) => ReflectType::List,
+        ReflectTypeInfo::Array(_) => ReflectType::Array,
+        ReflectTypeInfo::Map(_) => ReflectType::Map,
+        ReflectTypeInfo::Enum(_) => ReflectType::Enum,
+        ReflectTypeInfo::Value(_) | ReflectTypeInfo::Dynamic(_) => ReflectType::Value,
+    }
+}
+
+/// The kind of value a described type is built as, or [None] for value types, which can be
+/// written as any value.
+fn described_kind_of(kind: &ManifestKind) -> Option<ReflectType> {
+    match kind {
+        ManifestKind::Struct { .. } => Some(ReflectType::Struct),
+        ManifestKind::TupleStruct { .. } => Some(ReflectType::TupleStruct),
+        ManifestKind::Tuple { .. } => Some(ReflectType::Tuple),
+        ManifestKind::List | ManifestKind::Array => Some(ReflectType::List),
+        ManifestKind::Map => Some(ReflectType::Map),
+        ManifestKind::Enum { .. } => Some(ReflectType::Enum),
+        ManifestKind::Value => None,
+    }
+}
+
+/// Whether values of the type are written with their own syntax, ie: `Vec3 { x: 1.0 }`, so
+/// they're always parsed as the type itself.
+fn is_built_in(type_name: &str) -> bool {
+    [
+        std::any::type_name::<String>(),
+        std::any::type_name::<Vec2>(),
+        std::any::type_name::<Vec3>(),
+        std::any::type_name::<Quat>(),
+        std::any::type_name::<Color>(),
+    ]
+    .contains(&type_name)
+}
+
+/// Ensure `name` is one of the given field names, suggesting the closest field if it isn't.
+pub(crate) fn check_field_name(
+    type_name: &str,
+    name: &str,
+    fields: &[&str],
+) -> Result<(), LoadPrefabError> {
+    if fields.contains(&name) {
+        return Ok(());
+    }
+
+    // Only suggest fields that are a few typos away, and not completely different.
+    let suggestion = fields
+        .iter()
+        .map(|f| (edit_distance(name, f), f))
+        .filter(|(distance, f)| *distance <= (f.len() / 3).max(1) && *distance < f.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, f)| f.to_string());
+
+    Err(LoadPrefabError::UnknownField {
+        type_name: type_name.to_string(),
+        field: name.to_string(),
+        suggestion,
+        valid: fields.iter().map(|f| f.to_string()).collect(),
+    })
+}
+
+/// The number of single character edits needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev + usize::from(ca != *cb);
+            prev = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Returns the single generic argument of a type name, ie: `u8` from `alloc::vec::Vec<u8>`.
+fn generic_arg(type_name: &'static str, prefix: &str) -> Option<&'static str> {
+    type_name.strip_prefix(prefix)?.strip_suffix('>')
+}
+
+const PRIMITIVES: [&str; 17] = [
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "f32",
+    "f64",
+    "bool",
+    "char",
+    "alloc::string::String",
+];
+
+pub(crate) fn is_primitive(type_name: &str) -> bool {
+    PRIMITIVES.contains(&type_name)
+}
+
+macro_rules! parse_number {
+    ($digits:expr, $radix:expr, $type_name:expr, $($t:ty),*) => {
+        match $type_name {
+            $(stringify!($t) => <$t>::from_str_radix($digits, $radix)
+                .ok()
+                .map(|v| Box::new(v) as Box<dyn Reflect>),)*
+            "f32" if $radix == 10 => $digits.parse::<f32>().ok().map(|v| Box::new(v) as Box<dyn Reflect>),
+            "f64" if $radix == 10 => $digits.parse::<f64>().ok().map(|v| Box::new(v) as Box<dyn Reflect>),
+            _ => None,
+        }
+    };
+}
+
+const NUMBER_SUFFIXES: [&str; 14] = [
+    "u128", "i128", "usize", "isize", "u16", "u32", "u64", "i16", "i32", "i64", "f32", "f64", "u8",
+    "i8",
+];
+
+/// Split an explicit type suffix from a number literal, ie: `5u8` or `2.0f64`.
+fn split_suffix(text: &str) -> (&str, Option<&'static str>) {
+    let hex = text.trim_start_matches('-').starts_with("0x");
+    for suffix in NUMBER_SUFFIXES {
+        // Float suffixes are valid hex digits.
+        if hex && suffix.starts_with('f') {
+            continue;
+        }
+        if let Some(literal) = text.strip_suffix(suffix) {
+            return (literal, Some(suffix));
+        }
+    }
+    (text, None)
+}
+
+/// Returns the expected type for a literal, using it's suffix as the type if it has one.
+fn literal_type(expected: &ExpectedType, suffix: Option<&'static str>) -> ExpectedType {
+    match suffix {
+        Some(suffix) => ExpectedType::named(expected.path.clone(), suffix),
+        None => expected.clone(),
+    }
+}
+
+/// Parse an integer literal, ie: `10`, `10_000`, `0xFF`, `0b101` or `5u8`.
+///
+/// Integers without a suffix are parsed as the expected numeric type, or `i32` if the
+/// expected type isn't numeric.
+pub(crate) fn parse_int(
+    text: &str,
+    expected: &ExpectedType,
+) -> Result<Box<dyn Reflect>, LoadPrefabError> {
+    let (literal, suffix) = split_suffix(text);
+    let literal_type = literal_type(expected, suffix);
+    let type_name = match literal_type.primitive() {
+        Some(name) if NUMBER_SUFFIXES.contains(&name) => name,
+        _ => "i32",
+    };
+
+    let literal = literal.replace('_', "");
+    let (sign, unsigned) = match literal.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", literal.as_str()),
+    };
+    let (radix, digits) = if let Some(digits) = unsigned.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = unsigned.strip_prefix("0b") {
+        (2, digits)
+    } else {
+        (10, unsigned)
+    };
+    let digits = format!("{}{}", sign, digits);
+
+    parse_number!(
+        digits.as_str(),
+        radix,
+        type_name,
+        u8,
+        u16,
+        u32,
+        u64,
+        u128,
+        usize,
+        i8,
+        i16,
+        i32,
+        i64,
+        i128,
+        isize
+    )
+    .ok_or_else(|| literal_type.mismatch(text))
+}
+
+/// Parse a float literal, ie: `2.5`, `1_000.0` or `2.0f64`.
+///
+/// Floats without a suffix are parsed as `f64` if that's the expected type, otherwise as `f32`.
+pub(crate) fn parse_float(
+    text: &str,
+    expected: &ExpectedType,
+) -> Result<Box<dyn Reflect>, LoadPrefabError> {
+    let (literal, suffix) = split_suffix(text);
+    let literal_type = literal_type(expected, suffix);
+    let literal = literal.replace('_', "");
+
+    let value: Option<Box<dyn Reflect>> = match literal_type.primitive() {
+        Some("f64") => literal
+            .parse::<f64>()
+            .ok()
+            .map(|v| Box::new(v) as Box<dyn Reflect>),
+        _ => literal
+            .parse::<f32>()
+            .ok()
+            .map(|v| Box::new(v) as Box<dyn Reflect>),
+    };
+    value.ok_or_else(|| literal_type.mismatch(text))
+}
@@ -0,0 +1,529 @@
+//! Writes [Prefab]s back to *.prefab* text.
+
+use std::{fmt::Write, ops::Range};
+
+use bevy::{
+    prelude::*,
+    reflect::{DynamicStruct, ReflectRef, VariantType},
+    utils::get_short_name,
+};
+use thiserror::Error;
+
+use crate::prefab::{ParamRef, Prefab, PrefabBuildStep, PrefabComponent};
+
+const INDENT: &str = "    ";
+
+/// Errors that can occur when writing a [Prefab] as *.prefab* text.
+#[derive(Error, Debug)]
+pub enum WritePrefabError {
+    #[error("Error writing prefab - the entity {0:?} isn't a named entity in the prefab.")]
+    UnnamedEntity(Entity),
+    #[error("Error writing prefab - the float value {0} can't be written in a prefab.")]
+    NonFiniteFloat(String),
+    #[error("Error writing prefab - values of type {0} can't be written in a prefab.")]
+    UnsupportedType(String),
+}
+
+/// Write a prefab as *.prefab* text. See [Prefab::to_prefab_string].
+pub(crate) fn write_prefab(prefab: &Prefab) -> Result<String, WritePrefabError> {
+    let mut out = String::new();
+    write_prefab_at(&mut out, prefab, 0)?;
+    out.push('\n');
+    Ok(out)
+}
+
+fn write_prefab_at(
+    out: &mut String,
+    prefab: &Prefab,
+    depth: usize,
+) -> Result<(), WritePrefabError> {
+    let mut header = Vec::new();
+    header.extend(prefab.name.clone());
+    header.extend(prefab.entity_name.as_ref().map(|name| format!("#{}", name)));
+    header.push("{".to_string());
+    out.push_str(&header.join(" "));
+    out.push('\n');
+
+    let indent = INDENT.repeat(depth + 1);
+    if !prefab.params.is_empty() {
+        let params = prefab
+            .params
+            .iter()
+            .map(|param| {
+                let type_name = get_short_name(param.default.type_name());
+                let default = value_string(&*param.default, &[])?;
+                Ok(format!("{}: {} = {}", param.name, type_name, default))
+            })
+            .collect::<Result<Vec<_>, WritePrefabError>>()?;
+        writeln!(out, "{}params {{ {} }},", indent, params.join(", ")).unwrap();
+    }
+
+    // Children are spawned before any other steps are performed, so writing them last
+    // doesn't change the prefab.
+    let mut children = Vec::new();
+    for step in prefab.steps.iter() {
+        match step {
+            PrefabBuildStep::AddComponent(component) => {
+                writeln!(out, "{}{},", indent, component_string(component)?).unwrap();
+            }
+            PrefabBuildStep::RunCommand(command) => {
+                let properties = match &command.properties {
+                    Some(properties) => fields_string(properties, &[])?,
+                    None => String::new(),
+                };
+                writeln!(out, "{}{}!({}),", indent, command.name, properties).unwrap();
+            }
+            PrefabBuildStep::SpawnChild(child) => children.push(child),
+        }
+    }
+
+    if !children.is_empty() {
+        writeln!(out, "{}children: [", indent).unwrap();
+        for child in children {
+            out.push_str(&INDENT.repeat(depth + 2));
+            write_prefab_at(out, child, depth + 2)?;
+            out.push_str(",\n");
+        }
+        writeln!(out, "{}],", indent).unwrap();
+    }
+    out.push_str(&INDENT.repeat(depth));
+    out.push('}');
+    Ok(())
+}
+
+/// A component as it's written in a prefab, ie: `Transform { translation: Vec3 { x: 1.0 } }`.
+fn component_string(component: &PrefabComponent) -> Result<String, WritePrefabError> {
+    let refs = &component.entity_refs;
+    let reflect = &*component.reflect;
+    let name = &component.type_name;
+    let text = match reflect.reflect_ref() {
+        // Enum components are written as a variant, which includes the type name.
+        ReflectRef::Enum(_) => value_string(reflect, refs)?,
+        ReflectRef::Value(value) => format!("{}({})", name, value_string(value, refs)?),
+        _ => format!("{}{}", name, body_string(reflect, refs)?),
+    };
+    Ok(text)
+}
+
+/// The data of a struct, tuple struct, list or map, as it's written after the type name.
+fn body_string(value: &dyn Reflect, refs: &[String]) -> Result<String, WritePrefabError> {
+    let body = match value.reflect_ref() {
+        ReflectRef::Struct(value) => {
+            let fields = (0..value.field_len())
+                .map(|i| {
+                    let field = value_string(value.field_at(i).unwrap(), refs)?;
+                    Ok(format!("{}: {}", value.name_at(i).unwrap(), field))
+                })
+                .collect::<Result<Vec<_>, WritePrefabError>>()?;
+            braced(&fields)
+        }
+        ReflectRef::TupleStruct(value) => {
+            let fields = value
+                .iter_fields()
+                .map(|f| value_string(f, refs))
+                .collect::<Result<Vec<_>, WritePrefabError>>()?;
+            format!("({})", fields.join(", "))
+        }
+        ReflectRef::List(_) | ReflectRef::Array(_) => format!(" {}", value_string(value, refs)?),
+        ReflectRef::Map(value) => {
+            let entries = value
+                .iter()
+                .map(|(key, value)| {
+                    let (key, value) = (value_string(key, refs)?, value_string(value, refs)?);
+                    Ok(format!("{}: {}", key, value))
+                })
+                .collect::<Result<Vec<_>, WritePrefabError>>()?;
+            braced(&entries)
+        }
+        // Tuples have no syntax of their own in a prefab.
+        _ => return Err(unsupported(value)),
+    };
+    Ok(body)
+}
+
+fn braced(fields: &[String]) -> String {
+    match fields.is_empty() {
+        true => String::new(),
+        false => format!(" {{ {} }}", fields.join(", ")),
+    }
+}
+
+fn unsupported(value: &dyn Reflect) -> WritePrefabError {
+    WritePrefabError::UnsupportedType(value.type_name().to_string())
+}
+
+/// The fields of a struct, ie: build command properties, separated by commas.
+fn fields_string(value: &DynamicStruct, refs: &[String]) -> Result<String, WritePrefabError> {
+    let fields = (0..value.field_len())
+        .map(|i| {
+            let field = value_string(value.field_at(i).unwrap(), refs)?;
+            Ok(format!("{}: {}", value.name_at(i).unwrap(), field))
+        })
+        .collect::<Result<Vec<_>, WritePrefabError>>()?;
+    Ok(fields.join(", "))
+}
+
+/// A value as it's written in a prefab. `refs` are the names of the entities referred to by
+/// the component the value is in.
+///
+/// Returns an error for values that can't be read back from a prefab, such as entities that
+/// don't refer to a named entity in the prefab.
+pub(crate) fn value_string(
+    value: &dyn Reflect,
+    refs: &[String],
+) -> Result<String, WritePrefabError> {
+    if let Some(param) = value.downcast_ref::<ParamRef>() {
+        return Ok(format!("${}", param.0));
+    }
+    if let Some(entity) = value.downcast_ref::<Entity>() {
+        return match PrefabComponent::placeholder_index(*entity).and_then(|i| refs.get(i)) {
+            Some(name) => Ok(format!("@{}", name)),
+            None => Err(WritePrefabError::UnnamedEntity(*entity)),
+        };
+    }
+    if let Some(v) = value.downcast_ref::<Vec2>() {
+        let (x, y) = (float(v.x)?, float(v.y)?);
+        return Ok(format!("Vec2 {{ x: {}, y: {} }}", x, y));
+    }
+    if let Some(v) = value.downcast_ref::<Vec3>() {
+        let (x, y, z) = (float(v.x)?, float(v.y)?, float(v.z)?);
+        return Ok(format!("Vec3 {{ x: {}, y: {}, z: {} }}", x, y, z));
+    }
+    if let Some(q) = value.downcast_ref::<Quat>() {
+        let [x, y, z, w] = q.to_array();
+        let (x, y, z, w) = (float(x)?, float(y)?, float(z)?, float(w)?);
+        return Ok(format!("Quat {{ x: {}, y: {}, z: {}, w: {} }}", x, y, z, w));
+    }
+    if let Some(color) = value.downcast_ref::<Color>() {
+        let [r, g, b, a] = color.as_rgba_f32();
+        let (r, g, b, a) = (float(r)?, float(g)?, float(b)?, float(a)?);
+        return Ok(format!(
+            "Color {{ r: {}, g: {}, b: {}, a: {} }}",
+            r, g, b, a
+        ));
+    }
+    macro_rules! range {
+        ($($t:ty),*) => {
+            $(
+                if let Some(range) = value.downcast_ref::<Range<$t>>() {
+                    let (start, end) = (primitive_string(&range.start)?, primitive_string(&range.end)?);
+                    return Ok(format!("({}..{})", start, end));
+                }
+            )*
+        };
+    }
+    range!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+    let text = match value.reflect_ref() {
+        ReflectRef::List(list) => {
+            let items = list
+                .iter()
+                .map(|item| value_string(item, refs))
+                .collect::<Result<Vec<_>, WritePrefabError>>()?;
+            format!("[{}]", items.join(", "))
+        }
+        ReflectRef::Array(array) => {
+            let items = array
+                .iter()
+                .map(|item| value_string(item, refs))
+                .collect::<Result<Vec<_>, WritePrefabError>>()?;
+            format!("[{}]", items.join(", "))
+        }
+        ReflectRef::Enum(value) => {
+            let is_option = is_option(value.type_name());
+            let type_name = get_short_name(value.type_name());
+            let fields = value
+                .iter_fields()
+                .map(|field| {
+                    let text = value_string(field.value(), refs)?;
+                    Ok(match field.name() {
+                        Some(name) => format!("{}: {}", name, text),
+                        None => text,
+                    })
+                })
+                .collect::<Result<Vec<_>, WritePrefabError>>()?;
+            let fields = match value.variant_type() {
+                VariantType::Unit => String::new(),
+                VariantType::Tuple => format!("({})", fields.join(", ")),
+                VariantType::Struct => format!(" {{ {} }}", fields.join(", ")),
+            };
+            // Options are written without their type, ie: `Some(5)` or `None`.
+            match is_option {
+                true => format!("{}{}", value.variant_name(), fields),
+                false => format!("{}::{}{}", type_name, value.variant_name(), fields),
+            }
+        }
+        ReflectRef::Value(value) => primitive_string(value)?,
+        _ => {
+            let type_name = get_short_name(value.type_name());
+            format!("{}{}", type_name, body_string(value, refs)?)
+        }
+    };
+    Ok(text)
+}
+
+/// Whether an enum's type is `Option`, including the options built when parsing `Some(..)`
+/// or `None`, which don't have the full type name.
+fn is_option(type_name: &str) -> bool {
+    type_name == "Option" || type_name.starts_with("core::option::Option<")
+}
+
+/// A primitive value. Numbers that aren't `i32` or `f32` are written with a type suffix so
+/// they're read back as the same type in build command properties.
+fn primitive_string(value: &dyn Reflect) -> Result<String, WritePrefabError> {
+    macro_rules! suffixed {
+        ($($t:ty),*) => {
+            $(
+                if let Some(v) = value.downcast_ref::<$t>() {
+                    return Ok(format!("{}{}", v, stringify!($t)));
+                }
+            )*
+        };
+    }
+
+    if let Some(v) = value.downcast_ref::<i32>() {
+        return Ok(v.to_string());
+    }
+    if let Some(v) = value.downcast_ref::<f32>() {
+        return float(*v);
+    }
+    if let Some(v) = value.downcast_ref::<f64>() {
+        if !v.is_finite() {
+            return Err(WritePrefabError::NonFiniteFloat(v.to_string()));
+        }
+        return Ok(format!("{}f64", float_literal(format!("{:?}", v))));
+    }
+    suffixed!(u8, u16, u32, u64, u128, usize, i8, i16, i64, i128, isize);
+    if let Some(v) = value.downcast_ref::<bool>() {
+        return Ok(v.to_string());
+    }
+    if let Some(v) = value.downcast_ref::<char>() {
+        return Ok(format!("'{}'", escape(&v.to_string(), '\'')));
+    }
+    if let Some(v) = value.downcast_ref::<String>() {
+        return Ok(format!("\"{}\"", escape(v, '"')));
+    }
+    Err(unsupported(value))
+}
+
+/// Escape the text of a string or char, so it can be written between `quote`s.
+fn escape(text: &str, quote: char) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\\' => out.push_str("\\\\"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn float(value: f32) -> Result<String, WritePrefabError> {
+    match value.is_finite() {
+        true => Ok(float_literal(format!("{:?}", value))),
+        false => Err(WritePrefabError::NonFiniteFloat(value.to_string())),
+    }
+}
+
+/// Float literals must have a decimal point, ie: `1.0e-7` rather than `1e-7`.
+fn float_literal(text: String) -> String {
+    if text.contains('.') {
+        return text;
+    }
+    match text.find('e') {
+        Some(i) => format!("{}.0{}", &text[..i], &text[i..]),
+        None => format!("{}.0", text),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use bevy::prelude::*;
+
+    use super::WritePrefabError;
+    use crate::{
+        build_commands::InsertSpriteBundle,
+        parse::parse_prefab_file,
+        prefab::{PrefabBuildStep, PrefabComponent},
+        Prefab, PrefabRegistry,
+    };
+
+    #[derive(Debug, Default, PartialEq, Reflect, FromReflect)]
+    struct Stats {
+        level: u8,
+        speed: f64,
+        tags: Vec<String>,
+    }
+
+    /// Named like `Option`, but written with it's type like any other enum.
+    #[derive(Debug, Default, PartialEq, Reflect, FromReflect)]
+    enum OptionKind {
+        #[default]
+        Fixed,
+        Random(u8),
+    }
+
+    #[derive(Debug, Default, PartialEq, Reflect, Component)]
+    #[reflect(Component)]
+    struct Unit {
+        name: String,
+        stats: Stats,
+        target: Option<Entity>,
+        ids: Vec<i32>,
+        range: std::ops::Range<u8>,
+        initial: char,
+        kind: OptionKind,
+    }
+
+    /// The value a component step builds, applied to the component's default value.
+    fn component<T: Reflect + Default>(step: &PrefabBuildStep) -> (T, &[String]) {
+        let mut value = T::default();
+        match step {
+            PrefabBuildStep::AddComponent(component) => {
+                value.apply(&*component.reflect);
+                (value, &component.entity_refs)
+            }
+            _ => panic!("expected a component"),
+        }
+    }
+
+    fn child(prefab: &Prefab, index: usize) -> &Prefab {
+        match &prefab.steps[index] {
+            PrefabBuildStep::SpawnChild(child) => child,
+            _ => panic!("expected a child"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut registry = PrefabRegistry::default();
+        registry.register_type::<Unit>();
+        registry.register_type::<Stats>();
+        registry.register_type::<OptionKind>();
+        registry.register_type::<Transform>();
+        registry.register_type::<Visibility>();
+        registry.register_build_command::<InsertSpriteBundle>();
+
+        let input = "Knight #knight {
+            params { hp: i32 = 10, tint: Color = Color::RED },
+            Unit {
+                name: \"Sir \\\"Lance\\\"\\tof the Lake\\\\\",
+                stats: Stats { level: 3, speed: 1.5e-7, tags: [\"brave\", \"\"] },
+                target: Some(@horse),
+                ids: [1, 2],
+                range: (1..5),
+                initial: '\\'',
+                kind: OptionKind::Random(2),
+            },
+            InsertSpriteBundle!(color: $tint, texture_path: \"knight.png\"),
+            children: [
+                #horse { Transform { translation: Vec3 { y: 1.0 } }, Visibility },
+            ],
+        }";
+        let prefab = parse_prefab_file("knight.prefab", input, &registry).unwrap();
+        let text = prefab.to_prefab_string().unwrap();
+        let reparsed = parse_prefab_file("knight.prefab", &text, &registry).unwrap();
+        assert_eq!(reparsed.to_prefab_string().unwrap(), text);
+        assert!(
+            text.contains(r#"name: "Sir \"Lance\"\tof the Lake\\""#),
+            "{}",
+            text
+        );
+        assert!(text.contains("speed: 1.5e-7f64"), "{}", text);
+        assert!(text.contains("target: Some(@horse)"));
+        assert!(text.contains("level: 3u8"));
+        assert!(text.contains("range: (1u8..5u8)"));
+        assert!(text.contains(r"initial: '\''"));
+        assert!(text.contains("kind: OptionKind::Random(2u8)"), "{}", text);
+        assert!(text.starts_with("Knight #knight {\n    params { hp: i32 = 10, tint: Color"));
+
+        // The reparsed prefab has the same values as the original.
+        assert_eq!(reparsed.name, prefab.name);
+        assert_eq!(reparsed.entity_name, prefab.entity_name);
+        assert_eq!(reparsed.params.len(), 2);
+        for (param, original) in reparsed.params.iter().zip(prefab.params.iter()) {
+            assert_eq!(param.name, original.name);
+            assert_eq!(
+                param.default.reflect_partial_eq(&*original.default),
+                Some(true)
+            );
+        }
+        assert_eq!(reparsed.steps.len(), 3);
+
+        let (unit, refs) = component::<Unit>(&reparsed.steps[0]);
+        assert_eq!((unit, refs), component::<Unit>(&prefab.steps[0]));
+        assert_eq!(
+            component::<Unit>(&reparsed.steps[0]).0.name,
+            "Sir \"Lance\"\tof the Lake\\"
+        );
+        match (&reparsed.steps[1], &prefab.steps[1]) {
+            (PrefabBuildStep::RunCommand(command), PrefabBuildStep::RunCommand(original)) => {
+                assert_eq!(command.name, original.name);
+                let (properties, original) = (&command.properties, &original.properties);
+                let properties = properties.as_ref().unwrap();
+                let original = original.as_ref().unwrap();
+                assert_eq!(properties.reflect_partial_eq(original), Some(true));
+            }
+            _ => panic!("expected a command"),
+        }
+
+        let (horse, original) = (child(&reparsed, 2), child(&prefab, 2));
+        assert_eq!(horse.entity_name, original.entity_name);
+        assert_eq!(
+            component::<Transform>(&horse.steps[0]),
+            component::<Transform>(&original.steps[0])
+        );
+        assert_eq!(
+            component::<Visibility>(&horse.steps[1]).0.is_visible,
+            component::<Visibility>(&original.steps[1]).0.is_visible
+        );
+    }
+
+    #[test]
+    fn unwritable_values() {
+        let write = |reflect: Box<dyn Reflect>| {
+            let component = PrefabComponent {
+                type_name: "Value".to_string(),
+                reflect,
+                entity_refs: Vec::new(),
+                uses_params: false,
+            };
+            let prefab = Prefab {
+                name: None,
+                path: None,
+                base: None,
+                entity_name: None,
+                params: Vec::new(),
+                imports: Vec::new(),
+                steps: vec![PrefabBuildStep::AddComponent(Arc::new(component))],
+            };
+            prefab.to_prefab_string().unwrap_err()
+        };
+
+        assert!(matches!(
+            write(Box::new(Transform::from_xyz(f32::NAN, 0.0, 0.0))),
+            WritePrefabError::NonFiniteFloat(_)
+        ));
+        assert!(matches!(
+            write(Box::new(Some(Entity::from_raw(3)))),
+            WritePrefabError::UnnamedEntity(_)
+        ));
+        assert!(matches!(
+            write(Box::new((1, 2.0))),
+            WritePrefabError::UnsupportedType(_)
+        ));
+        assert!(matches!(
+            write(Box::new(std::time::Duration::from_secs(1))),
+            WritePrefabError::UnsupportedType(_)
+        ));
+    }
+}